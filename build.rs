@@ -0,0 +1,113 @@
+//! Embeds the files in `static/` into the binary.
+//!
+//! Generates `static_files.rs` in `OUT_DIR`, a `&[StaticFile]` expression
+//! that can be included with `include!`. Files that get smaller when
+//! compressed are embedded gzipped, too.
+
+use flate2::{write::GzEncoder, Compression};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const STATIC_DIR: &str = "static";
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let static_dir = manifest_dir.join(STATIC_DIR);
+
+    // Adding or removing a file changes the directory, too
+    println!("cargo:rerun-if-changed={}", static_dir.display());
+
+    let mut files = vec![];
+    if static_dir.is_dir() {
+        collect_files(&static_dir, &mut files);
+    }
+    files.sort();
+
+    let mut table = String::from("&[\n");
+    for file in files {
+        println!("cargo:rerun-if-changed={}", file.display());
+
+        let path = file
+            .strip_prefix(&static_dir)
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let content = fs::read(&file).unwrap();
+
+        let gzip_variant = match gzip(&content) {
+            Some(compressed) => {
+                let gzip_file = out_dir.join("gzip").join(format!("{}.gz", path));
+                fs::create_dir_all(gzip_file.parent().unwrap()).unwrap();
+                fs::write(&gzip_file, &compressed).unwrap();
+
+                format!(
+                    "Some((include_bytes!({:?}), {:?}))",
+                    gzip_file.to_str().unwrap(),
+                    etag(&compressed)
+                )
+            }
+            None => String::from("None"),
+        };
+
+        table.push_str(&format!(
+            "    StaticFile {{ path: {:?}, content: include_bytes!({:?}), etag: {:?}, gzip: {} }},\n",
+            path,
+            file.to_str().unwrap(),
+            etag(&content),
+            gzip_variant,
+        ));
+    }
+    table.push_str("]\n");
+
+    fs::write(out_dir.join("static_files.rs"), table).unwrap();
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        // Skips files like `.gitkeep` or editor swap files
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(true, |name| name.starts_with('.'))
+        {
+            continue;
+        }
+
+        if path.is_dir() {
+            println!("cargo:rerun-if-changed={}", path.display());
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// The gzipped content, if it is smaller than the original
+fn gzip(content: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    if compressed.len() < content.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+/// Same as `httpd::etag`, which can't be used from the build script
+fn etag(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("\"{:016x}\"", hash)
+}
@@ -22,14 +22,17 @@ use stm32f7_discovery::{
     print, println,
     system_clock::{self, Hz},
 };
-use stm32f7_httpd::httpd::{self, Request, Response, Routes};
+use stm32f7_httpd::httpd::{self, Method, Request, Response, ResponseBuilder, Routes};
 
 const SYSTICK_FREQ: Hz = Hz(20);
 const HEAP_SIZE: usize = 50 * 1024;
 
 const ETHER_ADDRESS: EthernetAddress = EthernetAddress([0x00, 0x08, 0xDC, 0xAB, 0xCD, 0xEF]);
 const IP_ADDRESS: IpAddress = IpAddress::Ipv4(Ipv4Address([192, 168, 1, 42]));
+const PREFIX_LEN: u8 = 24;
+const GATEWAY: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
 const PORT: u16 = 80;
+const MAX_CONNECTIONS: usize = 2;
 
 const PAGE_INDEX: &str = include_str!("wiki/index.html");
 const PAGE_VIEW: &str = include_str!("wiki/view.html");
@@ -107,26 +110,28 @@ fn main() -> ! {
         &mut ethernet_mac,
         ethernet_dma,
         ETHER_ADDRESS,
-        IP_ADDRESS,
+        httpd::NetworkConfig::new(IP_ADDRESS, PREFIX_LEN).gateway(GATEWAY),
         PORT,
+        MAX_CONNECTIONS,
         |request: &Request, _body| {
             println!("Got {} request on {}", request.method(), request.path());
 
             Routes::init(request)
-                .route("GET", "/", |_request, _args| {
+                .route(Method::Get, "/", |_request, _args| {
                     let mut links = String::new();
                     for (key, _content) in pages.iter() {
                         links.push_str(&format!("<li><a href=\"/view/{}\">{}</a></li>", key, key));
                     }
                     let source = PAGE_INDEX.replace("{{links}}", &links);
 
-                    let mut headers = BTreeMap::new();
-                    headers.insert("Content-Type".to_string(), "text/html".to_string());
-                    headers.insert("Content-Length".to_string(), source.len().to_string());
-
-                    Response::new(httpd::Status::OK, headers, source.as_bytes().to_vec())
+                    // Lets browsers revalidate instead of downloading the page again
+                    ResponseBuilder::new(httpd::Status::OK)
+                        .body_html(&source)
+                        .etag()
+                        .cache_control(httpd::CacheControl::NoCache)
+                        .finalize()
                 })
-                .route("GET", "/view/:page_name", |_request, args| {
+                .route(Method::Get, "/view/:page_name", |_request, args| {
                     let source = match pages.get(args.get("page_name").unwrap().as_str()) {
                         None => PAGE_NOTFOUND.to_string(),
                         Some(content) => PAGE_VIEW
@@ -134,19 +139,18 @@ fn main() -> ! {
                             .replace("{{content}}", content),
                     };
 
-                    let mut headers = BTreeMap::new();
-                    headers.insert("Content-Type".to_string(), "text/html".to_string());
-                    headers.insert("Content-Length".to_string(), source.len().to_string());
-
-                    Response::new(httpd::Status::OK, headers, source.as_bytes().to_vec())
+                    // Lets browsers revalidate instead of downloading the page again
+                    ResponseBuilder::new(httpd::Status::OK)
+                        .body_html(&source)
+                        .etag()
+                        .cache_control(httpd::CacheControl::NoCache)
+                        .finalize()
                 })
+                .method_not_allowed()
                 .catch_all(|_request, _args| {
-                    let mut headers = BTreeMap::new();
-                    headers.insert("Content-Type".to_string(), "text/html".to_string());
-                    headers.insert(
-                        "Content-Length".to_string(),
-                        PAGE_NOTFOUND.len().to_string(),
-                    );
+                    let mut headers = httpd::HeaderMap::new();
+                    headers.set_content_type("text/html");
+                    headers.set_content_length(PAGE_NOTFOUND.len());
 
                     Response::new(
                         httpd::Status::NotFound,
@@ -162,7 +166,9 @@ fn main() -> ! {
 
     loop {
         // Poll for packets and handle 'em
-        server.poll();
+        if let Err(e) = server.poll() {
+            println!("Connection aborted, {}", e);
+        }
     }
 }
 
@@ -0,0 +1,53 @@
+//! Constructor for running the server on the STM32F7-Discovery board
+
+use alloc::vec::Vec;
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
+use stm32f7::stm32f7x6::{ETHERNET_DMA, ETHERNET_MAC, RCC, SYSCFG};
+use stm32f7_discovery::ethernet::{self, PhyError};
+use stm32f7_discovery::system_clock;
+
+use super::{NetworkConfig, Request, Response, HTTPD};
+
+impl<F: FnMut(&Request, &Vec<u8>) -> Response> HTTPD<ethernet::EthernetDevice, F> {
+    /// Sets up the board's ethernet peripheral and creates a server on top of it.
+    ///
+    /// Time is taken from the `system_clock`, so the SysTick interrupt has to be
+    /// initialized beforehand.
+    pub fn new(
+        rcc: &mut RCC,
+        syscfg: &mut SYSCFG,
+        ethernet_mac: &mut ETHERNET_MAC,
+        ethernet_dma: ETHERNET_DMA,
+        ethernet_addr: EthernetAddress,
+        network_config: NetworkConfig,
+        port: u16,
+        max_connections: usize,
+        routes_callback: F,
+    ) -> Result<Self, PhyError> {
+        ethernet::EthernetDevice::new(
+            Default::default(),
+            Default::default(),
+            rcc,
+            syscfg,
+            ethernet_mac,
+            ethernet_dma,
+            ethernet_addr,
+        )
+        .map(|ethernet_device| {
+            HTTPD::with_device(
+                ethernet_device,
+                ethernet_addr,
+                network_config,
+                port,
+                max_connections,
+                system_clock_now,
+                routes_callback,
+            )
+        })
+    }
+}
+
+fn system_clock_now() -> Instant {
+    Instant::from_millis(system_clock::ms() as i64)
+}
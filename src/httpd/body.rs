@@ -0,0 +1,49 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+/// Source of a response body that is generated while it is being sent.
+///
+/// The server pulls from the stream whenever the TCP send buffer has room,
+/// so the whole body never has to fit into the heap at once.
+pub trait BodyStream {
+    /// Writes the next part of the body into `buffer` and returns the number
+    /// of bytes written. Returning 0 ends the body.
+    fn read(&mut self, buffer: &mut [u8]) -> usize;
+
+    /// Length of the whole body, if known in advance.
+    ///
+    /// Bodies of unknown length are sent with `Transfer-Encoding: chunked`.
+    fn length(&self) -> Option<usize> {
+        None
+    }
+}
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn BodyStream>),
+}
+
+impl Body {
+    /// Length of the body, if known in advance
+    pub fn length(&self) -> Option<usize> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len()),
+            Body::Stream(stream) => stream.length(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => write!(f, "Bytes({:?})", bytes),
+            Body::Stream(stream) => write!(f, "Stream(length: {:?})", stream.length()),
+        }
+    }
+}
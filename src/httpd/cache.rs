@@ -0,0 +1,250 @@
+use alloc::{format, string::String};
+use core::fmt;
+
+use super::body::Body;
+use super::method::Method;
+use super::request::Request;
+use super::response::Response;
+use super::status::Status;
+
+/// Policies for the `Cache-Control` header of responses
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum CacheControl {
+    /// Must not be stored at all
+    NoStore,
+    /// May be stored, but has to be revalidated (e.g. by its ETag) before use
+    NoCache,
+    /// May be used for the given number of seconds without revalidation
+    MaxAge(u32),
+    /// Like `MaxAge`, but only the browser may store it, not shared caches
+    PrivateMaxAge(u32),
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheControl::NoStore => write!(f, "no-store"),
+            CacheControl::NoCache => write!(f, "no-cache"),
+            CacheControl::MaxAge(seconds) => write!(f, "public, max-age={}", seconds),
+            CacheControl::PrivateMaxAge(seconds) => write!(f, "private, max-age={}", seconds),
+        }
+    }
+}
+
+/// Strong entity tag for `content`, including the quotes.
+///
+/// This is a 64 bit FNV-1a hash, the build script uses the same one for
+/// embedded files.
+pub fn etag(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("\"{:016x}\"", hash)
+}
+
+/// Replaces the response with `304 Not Modified` if the client's cached
+/// copy is still valid (RFC 7232, 6).
+///
+/// `If-Modified-Since` is only considered without `If-None-Match`.
+pub(super) fn evaluate_preconditions(request: &Request, response: &mut Response) {
+    match request.method() {
+        Method::Get | Method::Head => {}
+        _ => return,
+    }
+
+    if response.status != Status::OK {
+        return;
+    }
+
+    let not_modified = match request.headers().get("If-None-Match") {
+        Some(if_none_match) => match response.headers.get("ETag") {
+            Some(etag) => etag_list_matches(if_none_match, etag),
+            None => if_none_match.trim() == "*",
+        },
+        None => match (
+            request
+                .headers()
+                .get("If-Modified-Since")
+                .and_then(parse_http_date),
+            response
+                .headers
+                .get("Last-Modified")
+                .and_then(parse_http_date),
+        ) {
+            (Some(if_modified_since), Some(last_modified)) => last_modified <= if_modified_since,
+            _ => false,
+        },
+    };
+
+    if not_modified {
+        response.status = Status::NotModified;
+        response.body = Body::Bytes(vec![]);
+
+        // Only metadata that helps caches update their copy is kept (RFC 7232, 4.1)
+        for name in &[
+            "Content-Type",
+            "Content-Length",
+            "Content-Encoding",
+            "Content-Language",
+            "Transfer-Encoding",
+        ] {
+            response.headers.remove(name);
+        }
+    }
+}
+
+/// Weak comparison, as used for `If-None-Match` (RFC 7232, 2.3.2)
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        if tag.starts_with("W/") {
+            String::from(&tag[2..])
+        } else {
+            String::from(tag)
+        }
+    };
+
+    let etag = opaque(etag);
+
+    list.split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// Parses an IMF-fixdate like `Sun, 06 Nov 1994 08:49:37 GMT` into a tuple
+/// that orders like the date.
+///
+/// The obsolete formats are treated as invalid, so the header is ignored.
+fn parse_http_date(date: &str) -> Option<(u16, u8, u8, u8, u8, u8)> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let date = date.trim();
+    if date.len() != 29 || !date.is_ascii() || &date[3..5] != ", " || &date[25..] != " GMT" {
+        return None;
+    }
+
+    let month = MONTHS.iter().position(|month| *month == &date[8..11])? as u8 + 1;
+
+    Some((
+        date[12..16].parse().ok()?,
+        month,
+        date[5..7].parse().ok()?,
+        date[17..19].parse().ok()?,
+        date[20..22].parse().ok()?,
+        date[23..25].parse().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::response::ResponseBuilder;
+    use super::super::tests::request;
+    use super::*;
+
+    const LAST_MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn response(status: Status) -> Response {
+        ResponseBuilder::new(status)
+            .header("Content-Type", "text/plain")
+            .header("Last-Modified", LAST_MODIFIED)
+            .body(b"cached".to_vec())
+            .etag()
+            .finalize()
+    }
+
+    fn evaluate(method: Method, headers: &[(&str, &str)]) -> Response {
+        let mut response = response(Status::OK);
+        evaluate_preconditions(&request(method, "/", headers), &mut response);
+        response
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_http_date(LAST_MODIFIED),
+            Some((1994, 11, 6, 8, 49, 37))
+        );
+        assert!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT")
+                < parse_http_date("Mon, 07 Nov 1994 08:49:36 GMT")
+        );
+
+        // Obsolete RFC 850 and asctime formats
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:4x:37 GMT"), None);
+    }
+
+    #[test]
+    fn compares_etags_weakly() {
+        assert!(etag_list_matches("\"1\"", "\"1\""));
+        assert!(etag_list_matches("W/\"1\"", "\"1\""));
+        assert!(etag_list_matches("\"0\", W/\"1\"", "W/\"1\""));
+        assert!(etag_list_matches(" * ", "\"1\""));
+        assert!(!etag_list_matches("\"0\", \"2\"", "\"1\""));
+        assert!(!etag_list_matches("1", "\"1\""));
+    }
+
+    #[test]
+    fn answers_matching_etags_with_not_modified() {
+        let etag = etag(b"cached");
+        let response = evaluate(Method::Get, &[("If-None-Match", &etag)]);
+
+        assert_eq!(response.status, Status::NotModified);
+        assert_eq!(response.body.length(), Some(0));
+        assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+        assert_eq!(response.headers.get("Last-Modified"), Some(LAST_MODIFIED));
+        assert!(!response.headers.contains_key("Content-Type"));
+        assert!(!response.headers.contains_key("Content-Length"));
+
+        let response = evaluate(Method::Head, &[("If-None-Match", "\"0\", *")]);
+        assert_eq!(response.status, Status::NotModified);
+    }
+
+    #[test]
+    fn prefers_etags_over_dates() {
+        let response = evaluate(
+            Method::Get,
+            &[
+                ("If-None-Match", "\"0\""),
+                ("If-Modified-Since", "Mon, 07 Nov 1994 08:49:37 GMT"),
+            ],
+        );
+
+        assert_eq!(response.status, Status::OK);
+        assert_eq!(response.body.length(), Some(6));
+    }
+
+    #[test]
+    fn compares_modification_dates() {
+        let response = evaluate(Method::Get, &[("If-Modified-Since", LAST_MODIFIED)]);
+        assert_eq!(response.status, Status::NotModified);
+
+        let response = evaluate(
+            Method::Get,
+            &[("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")],
+        );
+        assert_eq!(response.status, Status::OK);
+
+        let response = evaluate(Method::Get, &[("If-Modified-Since", "yesterday")]);
+        assert_eq!(response.status, Status::OK);
+    }
+
+    #[test]
+    fn ignores_other_methods_and_statuses() {
+        let post = evaluate(Method::Post, &[("If-None-Match", "*")]);
+        assert_eq!(post.status, Status::OK);
+
+        let mut not_found = response(Status::NotFound);
+        evaluate_preconditions(
+            &request(Method::Get, "/", &[("If-None-Match", "*")]),
+            &mut not_found,
+        );
+        assert_eq!(not_found.status, Status::NotFound);
+    }
+}
@@ -0,0 +1,258 @@
+use alloc::vec::Vec;
+use core::{mem, str};
+
+use super::headers::HeaderMap;
+use super::limits::Limits;
+use super::parser::ParseError;
+
+// Chunk size lines and trailer fields longer than this are rejected
+const MAX_LINE_LENGTH: usize = 1024;
+
+/// Incremental decoder for `Transfer-Encoding: chunked` request bodies.
+#[derive(Clone, Debug)]
+pub struct ChunkedDecoder {
+    state: ChunkState,
+    body: Vec<u8>,
+    trailers: HeaderMap,
+    // Counted against the limits of the request head
+    trailers_size: usize,
+}
+
+#[derive(Clone, Debug)]
+enum ChunkState {
+    Size,
+    // Bytes of the current chunk that are still missing
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            body: vec![],
+            trailers: HeaderMap::new(),
+            trailers_size: 0,
+        }
+    }
+
+    /// Consumes as much of `input` as possible.
+    ///
+    /// Returns whether the body is complete. Anything after the last chunk is
+    /// left in `input`, as it belongs to the next request.
+    pub fn decode(&mut self, input: &mut Vec<u8>, limits: &Limits) -> Result<bool, ParseError> {
+        let mut position = 0;
+
+        let result = loop {
+            let available = &input[position..];
+
+            match self.state {
+                ChunkState::Size => match read_line(available)? {
+                    None => break Ok(false),
+                    Some(line) => {
+                        position += line.len() + 2;
+
+                        // Chunk extensions after ';' are ignored
+                        let size_field = line.split(|byte| *byte == b';').next().unwrap_or(line);
+                        let size = str::from_utf8(size_field)
+                            .ok()
+                            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                            .ok_or(ParseError::Malformed)?;
+
+                        self.state = if size == 0 {
+                            ChunkState::Trailers
+                        } else if size > limits.max_body_size - self.body.len() {
+                            break Err(ParseError::BodyTooLarge);
+                        } else {
+                            ChunkState::Data(size)
+                        };
+                    }
+                },
+                ChunkState::Data(remaining) => {
+                    if available.is_empty() {
+                        break Ok(false);
+                    }
+
+                    let read = remaining.min(available.len());
+                    self.body.extend_from_slice(&available[..read]);
+                    position += read;
+
+                    self.state = if read < remaining {
+                        ChunkState::Data(remaining - read)
+                    } else {
+                        ChunkState::DataEnd
+                    };
+                }
+                ChunkState::DataEnd => {
+                    if available.len() < 2 {
+                        break Ok(false);
+                    } else if !available.starts_with(b"\r\n") {
+                        break Err(ParseError::Malformed);
+                    }
+
+                    position += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailers => match read_line(available)? {
+                    None => break Ok(false),
+                    Some(line) if line.is_empty() => {
+                        position += 2;
+                        self.state = ChunkState::Done;
+                    }
+                    Some(line) => {
+                        position += line.len() + 2;
+
+                        self.trailers_size += line.len() + 2;
+                        if self.trailers.len() == limits.max_header_count
+                            || self.trailers_size > limits.max_head_size
+                        {
+                            break Err(ParseError::HeadTooLarge);
+                        }
+
+                        let (key, value) = parse_trailer(line)?;
+                        self.trailers.append(key, value);
+                    }
+                },
+                ChunkState::Done => break Ok(true),
+            }
+        };
+
+        input.drain(..position);
+        result
+    }
+
+    /// The decoded body and trailer fields
+    pub fn finish(&mut self) -> (Vec<u8>, HeaderMap) {
+        (
+            mem::replace(&mut self.body, vec![]),
+            mem::replace(&mut self.trailers, HeaderMap::new()),
+        )
+    }
+}
+
+/// Returns the next line without its CRLF, if it is complete.
+fn read_line(input: &[u8]) -> Result<Option<&[u8]>, ParseError> {
+    match input.windows(2).position(|window| window == b"\r\n") {
+        Some(end) if end <= MAX_LINE_LENGTH => Ok(Some(&input[..end])),
+        None if input.len() <= MAX_LINE_LENGTH => Ok(None),
+        _ => Err(ParseError::Malformed),
+    }
+}
+
+fn parse_trailer(line: &[u8]) -> Result<(&str, &str), ParseError> {
+    let line = str::from_utf8(line).map_err(|_| ParseError::Malformed)?;
+    let separator = line.find(':').ok_or(ParseError::Malformed)?;
+
+    Ok((line[..separator].trim(), line[separator + 1..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_chunks() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut input = b"5\r\nHello\r\n7\r\n, world\r\n0\r\n\r\nGET".to_vec();
+
+        assert_eq!(decoder.decode(&mut input, &Limits::default()), Ok(true));
+        // The next request is left alone
+        assert_eq!(input, b"GET");
+
+        let (body, trailers) = decoder.finish();
+        assert_eq!(body, b"Hello, world");
+        assert!(trailers.is_empty());
+    }
+
+    #[test]
+    fn waits_for_split_input() {
+        // Sizes, extensions, data and line breaks are all cut somewhere
+        let parts: &[&[u8]] = &[
+            b"1",
+            b"0\r",
+            b"\n0123456789ab",
+            b"cdef\r",
+            b"\n1;na",
+            b"me=\"value\"\r\n!\r\n0\r",
+            b"\n\r",
+            b"\n",
+        ];
+        let mut decoder = ChunkedDecoder::new();
+        let mut input = vec![];
+
+        for (i, part) in parts.iter().enumerate() {
+            input.extend_from_slice(part);
+            let complete = decoder.decode(&mut input, &Limits::default());
+            assert_eq!(complete, Ok(i == parts.len() - 1));
+        }
+
+        assert!(input.is_empty());
+        assert_eq!(decoder.finish().0, b"0123456789abcdef!".as_ref());
+    }
+
+    #[test]
+    fn reads_trailers() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut input = b"3\r\nabc\r\n0\r\nExpires: never\r\nX-Checksum:  1234 \r\n\r\n".to_vec();
+
+        assert_eq!(decoder.decode(&mut input, &Limits::default()), Ok(true));
+
+        let (body, trailers) = decoder.finish();
+        assert_eq!(body, b"abc");
+        assert_eq!(trailers.get("expires"), Some("never"));
+        assert_eq!(trailers.get("X-Checksum"), Some("1234"));
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let long_line = [b'1'; MAX_LINE_LENGTH + 1];
+        let inputs: &[&[u8]] = &[
+            b"x\r\n",
+            b"-1\r\n",
+            b"3\r\nabcd\r\n",
+            b"0\r\nno colon\r\n\r\n",
+            &long_line,
+        ];
+
+        for input in inputs {
+            let mut input = input.to_vec();
+            let result = ChunkedDecoder::new().decode(&mut input, &Limits::default());
+            assert_eq!(result, Err(ParseError::Malformed));
+        }
+    }
+
+    #[test]
+    fn limits_body_size() {
+        let limits = Limits {
+            max_body_size: 8,
+            ..Limits::default()
+        };
+        let mut input = b"5\r\nHello\r\n4\r\n".to_vec();
+
+        let result = ChunkedDecoder::new().decode(&mut input, &limits);
+        assert_eq!(result, Err(ParseError::BodyTooLarge));
+    }
+
+    #[test]
+    fn limits_trailers() {
+        let limits = Limits {
+            max_header_count: 2,
+            ..Limits::default()
+        };
+        let mut input = b"0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".to_vec();
+
+        let result = ChunkedDecoder::new().decode(&mut input, &limits);
+        assert_eq!(result, Err(ParseError::HeadTooLarge));
+
+        let limits = Limits {
+            max_head_size: 16,
+            ..Limits::default()
+        };
+        let mut input = b"0\r\nA: 01234\r\nB: 01234\r\n\r\n".to_vec();
+
+        let result = ChunkedDecoder::new().decode(&mut input, &limits);
+        assert_eq!(result, Err(ParseError::HeadTooLarge));
+    }
+}
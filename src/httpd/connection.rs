@@ -0,0 +1,658 @@
+use alloc::{borrow::ToOwned, boxed::Box, format, vec::Vec};
+use core::{fmt, mem};
+use log::{debug, trace};
+use smoltcp::socket::{SocketHandle, TcpSocket};
+use smoltcp::time::Instant;
+
+use super::body::{Body, BodyStream};
+use super::chunked::ChunkedDecoder;
+use super::error::Error;
+use super::events::EventStream;
+use super::limits::Limits;
+use super::method::Method;
+use super::parser::{HTTPParser, ParseError};
+use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::send_buffer::SendBuffer;
+use super::status::Status;
+use super::websocket::WebSocket;
+
+/// State of a single listening TCP socket of the server.
+///
+/// Every socket in the pool gets its own input buffer and request state, so
+/// that multiple clients can be served at the same time.
+pub struct Connection {
+    tcp_handle: SocketHandle,
+    connected: bool,
+    input_buffer: Vec<u8>,
+    // Interim responses that are sent before the final response
+    interim_output: SendBuffer,
+    head_parser: HTTPParser,
+    request_state: RequestState,
+    keep_alive: bool,
+    // Time anything was last received or sent completely
+    last_activity: Instant,
+    // Time the first byte of the current request head was received
+    head_started: Option<Instant>,
+    // State that takes over the connection once the response is sent
+    takeover: Option<RequestState>,
+}
+
+impl Connection {
+    pub fn new(tcp_handle: SocketHandle) -> Connection {
+        Connection {
+            tcp_handle,
+            connected: false,
+            input_buffer: vec![],
+            interim_output: SendBuffer::new(),
+            head_parser: HTTPParser::new(),
+            request_state: RequestState::Wait,
+            keep_alive: false,
+            last_activity: Instant::from_millis(0),
+            head_started: None,
+            takeover: None,
+        }
+    }
+
+    pub fn tcp_handle(&self) -> SocketHandle {
+        self.tcp_handle
+    }
+
+    pub fn websocket_mut(&mut self) -> Option<&mut WebSocket> {
+        match &mut self.request_state {
+            RequestState::WebSocket(websocket) => Some(websocket),
+            _ => None,
+        }
+    }
+
+    pub fn event_stream_mut(&mut self) -> Option<&mut EventStream> {
+        match &mut self.request_state {
+            RequestState::EventStream(events) => Some(events),
+            _ => None,
+        }
+    }
+
+    pub fn poll<F: FnMut(&mut Request, &Vec<u8>) -> Response>(
+        &mut self,
+        socket: &mut TcpSocket,
+        port: u16,
+        timestamp: Instant,
+        limits: &Limits,
+        handler: &mut F,
+    ) -> Result<(), Error> {
+        let poll_status = self
+            .check_timeouts(timestamp, limits)
+            .and_then(|()| self.poll_socket(socket, port, timestamp, limits, handler))
+            .map_err(|e| {
+                debug!("Aborting connection: {}", e);
+                socket.abort();
+                e
+            })?;
+
+        match poll_status {
+            PollStatus::Established => self.request_init(timestamp),
+            PollStatus::Received(data) => self.request_receive(data, timestamp, limits),
+            PollStatus::Closed => self.request_close(),
+            PollStatus::Inactive => (),
+        }
+
+        Ok(())
+    }
+
+    fn request_init(&mut self, timestamp: Instant) {
+        debug!("Connection opened");
+        self.input_buffer = vec![];
+        self.interim_output = SendBuffer::new();
+        self.head_parser = HTTPParser::new();
+        self.request_state = RequestState::ReadHead;
+        self.keep_alive = false;
+        self.last_activity = timestamp;
+        self.head_started = None;
+        self.takeover = None;
+    }
+
+    /// Stops reading requests from clients that are too slow.
+    ///
+    /// WebSockets are pinged when idle instead, and closed if the client
+    /// doesn't answer until the next timeout. Responses the client doesn't
+    /// read fail with `Error::Stalled`.
+    fn check_timeouts(&mut self, timestamp: Instant, limits: &Limits) -> Result<(), Error> {
+        let idle_timed_out = timestamp - self.last_activity > limits.idle_timeout;
+        let want_receive = self.want_receive();
+
+        match &mut self.request_state {
+            RequestState::WebSocket(websocket) => {
+                if idle_timed_out {
+                    self.last_activity = timestamp;
+
+                    if !websocket.ping() {
+                        debug!("WebSocket timed out");
+                        self.request_state = RequestState::Wait;
+                    }
+                }
+                return Ok(());
+            }
+            // The send buffer can't be freed while the client doesn't read
+            RequestState::SendBody(_) if idle_timed_out => return Err(Error::Stalled),
+            _ if !want_receive => return Ok(()),
+            _ => {}
+        }
+
+        let header_timed_out = self
+            .head_started
+            .map(|head_started| timestamp - head_started > limits.header_read_timeout)
+            .unwrap_or(false);
+
+        if !header_timed_out && !idle_timed_out {
+            return Ok(());
+        }
+
+        let partial_request = match self.request_state {
+            RequestState::ReadHead => self.head_started.is_some(),
+            _ => true,
+        };
+
+        self.request_state = if partial_request {
+            debug!("Request timed out");
+            RequestState::ParseError(ParseError::Timeout)
+        } else {
+            // Connections without a request are closed without a response
+            debug!("Connection idle");
+            RequestState::Wait
+        };
+
+        Ok(())
+    }
+
+    fn request_receive(&mut self, chunk: Vec<u8>, timestamp: Instant, limits: &Limits) {
+        debug!("Received {} bytes", chunk.len());
+
+        self.input_buffer.extend(chunk);
+        self.last_activity = timestamp;
+
+        if let RequestState::ReadHead = self.request_state {
+            self.head_started = self.head_started.or(Some(timestamp));
+        }
+
+        match &self.request_state {
+            RequestState::ReadHead => self.read_head(limits),
+            RequestState::ReadBody(request, content_length) => {
+                self.read_body(request.clone(), *content_length)
+            }
+            RequestState::ReadChunkedBody(_, _) => self.read_chunked_body(limits),
+            RequestState::WebSocket(_) => self.read_frames(limits),
+            state => trace!("Can't receive in state {:?}", state),
+        }
+    }
+
+    fn read_body(&mut self, request: Request, content_length: usize) {
+        self.request_state = if self.input_buffer.len() >= content_length {
+            // Anything after the body belongs to the next (pipelined) request
+            let rest = self.input_buffer.split_off(content_length);
+            let body = mem::replace(&mut self.input_buffer, rest);
+            RequestState::RequestRead(request, body)
+        } else {
+            RequestState::ReadBody(request, content_length)
+        }
+    }
+
+    fn read_frames(&mut self, limits: &Limits) {
+        if let RequestState::WebSocket(websocket) = &mut self.request_state {
+            websocket.receive_frames(&mut self.input_buffer, limits.max_body_size);
+        }
+    }
+
+    fn read_chunked_body(&mut self, limits: &Limits) {
+        let request_state = mem::replace(&mut self.request_state, RequestState::Wait);
+
+        self.request_state = match request_state {
+            RequestState::ReadChunkedBody(mut request, mut decoder) => {
+                match decoder.decode(&mut self.input_buffer, limits) {
+                    Ok(true) => {
+                        let (body, trailers) = decoder.finish();
+                        request.set_trailers(trailers);
+                        RequestState::RequestRead(request, body)
+                    }
+                    Ok(false) => RequestState::ReadChunkedBody(request, decoder),
+                    Err(error) => {
+                        trace!("Could not decode chunked body: {:?}", error);
+                        RequestState::ParseError(error)
+                    }
+                }
+            }
+            request_state => request_state,
+        }
+    }
+
+    fn read_head(&mut self, limits: &Limits) {
+        match self.head_parser.parse_head(&self.input_buffer, limits) {
+            Ok((request_head, head_length)) => {
+                debug!("Request head parsed.");
+
+                self.input_buffer.drain(..head_length);
+                self.head_parser = HTTPParser::new();
+                self.head_started = None;
+                self.keep_alive = request_head.keep_alive();
+
+                // Chunked is the only transfer coding we decode, anything else
+                // would leave the length of the body unknown (RFC 7230, 3.3.1)
+                let chunked = if request_head.headers().contains_key("Transfer-Encoding") {
+                    let mut codings = request_head
+                        .headers()
+                        .get_all("Transfer-Encoding")
+                        .flat_map(|value| value.split(','))
+                        .map(str::trim)
+                        .filter(|coding| !coding.is_empty());
+
+                    match (codings.next(), codings.next()) {
+                        (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => true,
+                        _ => {
+                            self.request_state =
+                                RequestState::ParseError(ParseError::UnsupportedTransferCoding);
+                            return;
+                        }
+                    }
+                } else {
+                    false
+                };
+
+                // An invalid or ambiguous length can't be recovered from (RFC 7230, 3.3.3)
+                let content_length_res = request_head.headers().content_length();
+                if !chunked
+                    && content_length_res.is_none()
+                    && request_head.headers().contains_key("Content-Length")
+                {
+                    self.request_state = RequestState::ParseError(ParseError::Malformed);
+                    return;
+                }
+
+                // Expectations are only defined for HTTP/1.1 (RFC 7231, 5.1.1)
+                let expect_continue = match request_head.headers().get("Expect") {
+                    Some(_) if request_head.version() != "HTTP/1.1" => false,
+                    Some(expect) if expect.eq_ignore_ascii_case("100-continue") => true,
+                    Some(_) => {
+                        self.request_state =
+                            RequestState::ParseError(ParseError::UnknownExpectation);
+                        return;
+                    }
+                    None => false,
+                };
+
+                match (chunked, content_length_res) {
+                    (true, _) => {
+                        // Both lengths might be an attempt to smuggle a request
+                        // past a proxy, so the connection ends after this one
+                        if request_head.headers().contains_key("Content-Length") {
+                            self.keep_alive = false;
+                        }
+
+                        if expect_continue {
+                            self.send_continue();
+                        }
+
+                        self.request_state =
+                            RequestState::ReadChunkedBody(request_head, ChunkedDecoder::new());
+                        self.read_chunked_body(limits);
+                    }
+                    (false, Some(content_length)) if content_length > limits.max_body_size => {
+                        self.request_state = RequestState::ParseError(ParseError::BodyTooLarge)
+                    }
+                    (false, Some(content_length)) => {
+                        if expect_continue && content_length > 0 {
+                            self.send_continue();
+                        }
+
+                        self.read_body(request_head, content_length)
+                    }
+                    (false, None) => {
+                        self.request_state = RequestState::RequestRead(request_head, vec![])
+                    }
+                }
+            }
+            Err(ParseError::NotEnoughInput) => {
+                trace!("Request header incomplete");
+            }
+            Err(error) => {
+                trace!("Could not parse request header: {:?}", error);
+                self.request_state = RequestState::ParseError(error);
+            }
+        }
+    }
+
+    /// Tells the client to send the body it announced with `Expect: 100-continue`.
+    ///
+    /// Oversized bodies are rejected before this is called.
+    fn send_continue(&mut self) {
+        // The client may not have waited for us
+        if self.input_buffer.is_empty() {
+            debug!("Sending 100 Continue");
+            self.interim_output.extend(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+    }
+
+    fn request_close(&mut self) {
+        debug!("Connection closed");
+        self.request_state = RequestState::Wait;
+    }
+
+    fn want_receive(&self) -> bool {
+        match self.request_state {
+            RequestState::ReadHead
+            | RequestState::ReadBody(_, _)
+            | RequestState::ReadChunkedBody(_, _)
+            | RequestState::WebSocket(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Serializes the response, `head_only` drops the body for `HEAD` requests.
+    ///
+    /// Streams of unknown length are sent chunked if `chunked_allowed`, else
+    /// the end of the body is signaled by closing the connection. The same
+    /// goes for event streams, which are written after the response.
+    fn send_response(&mut self, mut response: Response, head_only: bool, chunked_allowed: bool) {
+        // Line breaks in the reason phrase would end the status line early
+        if !response.status.is_valid() {
+            debug!("Invalid status {:?}", response.status);
+            response = ResponseBuilder::new(Status::InternalServerError).finalize();
+        }
+
+        let allows_body = response.status.allows_body();
+        let length = match self.takeover {
+            Some(RequestState::EventStream(_)) => None,
+            _ => response.body.length(),
+        };
+        let chunked = allows_body && length.is_none() && chunked_allowed;
+
+        if allows_body && length.is_none() && !chunked_allowed {
+            self.keep_alive = false;
+        }
+
+        // The route may ask for the connection to be closed, too
+        self.keep_alive = self.keep_alive && !response.headers.connection("close");
+
+        // Upgrades keep their `Connection: Upgrade`
+        let upgrade = match self.takeover {
+            Some(RequestState::WebSocket(_)) => true,
+            _ => false,
+        };
+
+        if !upgrade {
+            let connection = if self.keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            };
+            response.headers.insert("Connection", connection);
+        }
+
+        // Without a length, the client couldn't tell where the body ends.
+        // Responses that can't have a body don't get one (RFC 7230, 3.3.2).
+        // Lengths set before are replaced, `after` hooks may swap the body.
+        if chunked {
+            response.headers.remove("Content-Length");
+            response.headers.insert("Transfer-Encoding", "chunked");
+        } else if let Some(length) = length.filter(|_| allows_body) {
+            response.headers.set_content_length(length);
+        }
+
+        let (status_num, status_text) = response.status.numerical_and_text();
+
+        trace!("Sending headers: {:?}", response.headers);
+
+        // The head is sent along with the body, so that it can be retried
+        // in the same way when the send buffer is full
+        // Interim responses that haven't been sent yet have to come first
+        let mut pending = mem::replace(&mut self.interim_output, SendBuffer::new());
+        pending.extend(format!("HTTP/1.1 {} {}\r\n", status_num, status_text).as_bytes());
+
+        for (key, value) in response.headers {
+            pending.extend(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+
+        pending.extend(b"\r\n");
+
+        // The length of the body is still announced
+        let stream = match response.body {
+            _ if head_only || !allows_body => None,
+            Body::Bytes(bytes) => {
+                pending.extend(&bytes);
+                None
+            }
+            Body::Stream(stream) => Some(stream),
+        };
+
+        debug!("Sending body");
+        self.request_state = RequestState::SendBody(Output {
+            pending,
+            stream,
+            chunked,
+            bytes_sent: 0,
+        });
+    }
+
+    fn poll_socket<F: FnMut(&mut Request, &Vec<u8>) -> Response>(
+        &mut self,
+        socket: &mut TcpSocket,
+        port: u16,
+        timestamp: Instant,
+        limits: &Limits,
+        handler: &mut F,
+    ) -> Result<PollStatus, Error> {
+        let old_connection_status = self.connected;
+        self.connected = socket.is_active();
+
+        if old_connection_status != self.connected {
+            return Ok(if self.connected {
+                PollStatus::Established
+            } else {
+                PollStatus::Closed
+            });
+        }
+
+        if !socket.is_open() {
+            socket.listen(port).map_err(Error::Listen)?;
+            debug!("Listening...");
+        }
+
+        self.interim_output.send(socket)?;
+
+        // WebSockets send and receive at the same time
+        if let RequestState::WebSocket(websocket) = &mut self.request_state {
+            websocket.send_output(socket)?;
+
+            if websocket.is_closed() {
+                debug!("WebSocket closed");
+                socket.close();
+                self.request_state = RequestState::Wait;
+            }
+        }
+
+        // Event streams don't read, but the client closing its side ends them
+        if let RequestState::EventStream(_) = self.request_state {
+            if !socket.may_recv() {
+                debug!("Event stream closed by the client");
+                socket.close();
+                self.request_state = RequestState::Wait;
+            }
+        }
+
+        if socket.may_recv() && self.want_receive() {
+            let data = socket
+                .recv(|recv_buffer| (recv_buffer.len(), recv_buffer.to_owned()))
+                .map_err(Error::Receive)?;
+
+            if data.len() > 0 {
+                return Ok(PollStatus::Received(data));
+            }
+        } else if socket.may_send() {
+            match &mut self.request_state {
+                RequestState::RequestRead(request, body) => {
+                    debug!("Request head:");
+                    debug!("{:?}", request);
+                    debug!("Body:");
+                    debug!("{:?}", body);
+
+                    let head_only = *request.method() == Method::Head;
+                    let chunked_allowed = request.version() == "HTTP/1.1";
+                    let response = handler(request, body);
+
+                    self.takeover = WebSocket::upgrade(request, &response)
+                        .map(RequestState::WebSocket)
+                        .or_else(|| {
+                            EventStream::open(request, &response).map(RequestState::EventStream)
+                        });
+
+                    self.send_response(response, head_only, chunked_allowed);
+                }
+                RequestState::ParseError(error) => {
+                    let response = error_response(error);
+
+                    // Framing of anything after a broken head is unknown
+                    self.keep_alive = false;
+                    self.send_response(response, false, false);
+                }
+                RequestState::SendBody(output) => {
+                    let bytes_sent = output.bytes_sent;
+                    let complete = output.send(socket)?;
+
+                    // Only a client that doesn't read at all times out
+                    if output.bytes_sent > bytes_sent {
+                        self.last_activity = timestamp;
+                    }
+
+                    if !complete {
+                        trace!("{} bytes remaining", output.pending.len());
+                    } else if let Some(takeover) = self.takeover.take() {
+                        let chunked = output.chunked;
+                        self.request_state = takeover;
+                        self.last_activity = timestamp;
+
+                        match &mut self.request_state {
+                            RequestState::WebSocket(_) => {
+                                debug!("Upgraded to WebSocket");
+
+                                // The client may send frames right after the handshake
+                                if !self.input_buffer.is_empty() {
+                                    self.read_frames(limits);
+                                }
+                            }
+                            RequestState::EventStream(events) => {
+                                debug!("Streaming events");
+                                events.start(chunked, timestamp);
+                            }
+                            _ => {}
+                        }
+                    } else if self.keep_alive {
+                        debug!("Keeping connection alive");
+                        self.request_state = RequestState::ReadHead;
+                        self.last_activity = timestamp;
+
+                        // A pipelined request may already be waiting in the buffer
+                        if !self.input_buffer.is_empty() {
+                            self.head_started = Some(timestamp);
+                            self.read_head(limits);
+                        }
+                    } else {
+                        socket.close();
+                    }
+                }
+                // Events are queued by `HTTPD::poll`
+                RequestState::EventStream(events) => {
+                    events.send_output(socket)?;
+                }
+                _ => {
+                    debug!("Request not read");
+                    socket.close();
+                }
+            }
+        }
+
+        Ok(PollStatus::Inactive)
+    }
+}
+
+enum PollStatus {
+    Established,
+    Received(Vec<u8>),
+    Closed,
+    Inactive,
+}
+
+#[derive(Debug)]
+enum RequestState {
+    Wait,
+    ReadHead,
+    // Request head and announced content length
+    ReadBody(Request, usize),
+    ReadChunkedBody(Request, ChunkedDecoder),
+    RequestRead(Request, Vec<u8>),
+    SendBody(Output),
+    ParseError(ParseError),
+    WebSocket(WebSocket),
+    EventStream(EventStream),
+}
+
+// Size of the parts that are read from body streams at once
+const STREAM_CHUNK_SIZE: usize = 512;
+
+/// Response that is being sent.
+struct Output {
+    // The head and the body, as far as it was read from the stream
+    pending: SendBuffer,
+    stream: Option<Box<dyn BodyStream>>,
+    chunked: bool,
+    bytes_sent: usize,
+}
+
+impl Output {
+    /// Sends as much as possible, returns whether the response is complete.
+    fn send(&mut self, socket: &mut TcpSocket) -> Result<bool, Error> {
+        loop {
+            self.bytes_sent += self.pending.send(socket)?;
+
+            if !self.pending.is_empty() {
+                return Ok(false);
+            }
+
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => return Ok(true),
+            };
+
+            let mut data = vec![0; STREAM_CHUNK_SIZE];
+            let read = stream.read(&mut data);
+            data.truncate(read);
+
+            if read == 0 {
+                self.stream = None;
+
+                if self.chunked {
+                    self.pending.extend(b"0\r\n\r\n");
+                }
+            } else if self.chunked {
+                self.pending.extend(format!("{:x}\r\n", read).as_bytes());
+                self.pending.extend(&data);
+                self.pending.extend(b"\r\n");
+            } else {
+                self.pending.extend(&data);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Output({} bytes pending, streaming: {}, chunked: {})",
+            self.pending.len(),
+            self.stream.is_some(),
+            self.chunked
+        )
+    }
+}
+
+fn error_response(error: &ParseError) -> Response {
+    ResponseBuilder::new(error.status()).finalize()
+}
@@ -0,0 +1,315 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::headers::join_list;
+use super::method::Method;
+use super::middleware::Middleware;
+use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::status::Status;
+
+/// Middleware implementing Cross-Origin Resource Sharing.
+///
+/// Preflight requests are answered directly, without calling the routes.
+/// By default, any origin may send `GET`, `HEAD` and `POST` requests without
+/// custom headers or credentials.
+pub struct Cors {
+    // `None` allows any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u32>,
+}
+
+impl Cors {
+    pub fn new() -> Cors {
+        Cors {
+            origins: None,
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allows requests from `origin` (e.g. `http://192.168.1.10:8080`).
+    ///
+    /// Once an origin is added, only the added origins are allowed.
+    #[allow(dead_code)]
+    pub fn origin(mut self, origin: &str) -> Self {
+        self.origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.to_string());
+        self
+    }
+
+    /// Replaces the methods that can be used in cross-origin requests.
+    pub fn methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Allows the request header `name`, e.g. `Content-Type` for JSON bodies.
+    pub fn header(mut self, name: &str) -> Self {
+        self.headers.push(name.to_string());
+        self
+    }
+
+    /// Allows cookies and authentication in cross-origin requests.
+    #[allow(dead_code)]
+    pub fn credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// How long browsers may cache the result of a preflight request.
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        match &self.origins {
+            None => true,
+            Some(origins) => origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    fn preflight_allowed(&self, request: &Request, method: &str) -> bool {
+        let method_allowed = self
+            .methods
+            .iter()
+            .any(|allowed| allowed.as_str() == method);
+
+        let headers_allowed = request
+            .headers()
+            .get_all("Access-Control-Request-Headers")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                self.headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            });
+
+        method_allowed && headers_allowed
+    }
+
+    /// Sets the headers that are part of every response to an allowed origin.
+    fn allow_origin(&self, origin: &str, response: &mut Response) {
+        // With credentials, `*` isn't accepted by browsers (Fetch, 3.2.5)
+        if self.origins.is_none() && !self.credentials {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response
+                .headers
+                .insert("Access-Control-Allow-Origin", origin);
+
+            // Caches have to keep responses for different origins apart
+            if !response.headers.contains_token("Vary", "Origin") {
+                response.headers.append("Vary", "Origin");
+            }
+        }
+
+        if self.credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&mut self, request: &mut Request) -> Option<Response> {
+        if !is_preflight(request) {
+            return None;
+        }
+
+        let mut response = ResponseBuilder::new(Status::NoContent).finalize();
+
+        // A rejected preflight is answered without CORS headers, so that the
+        // browser doesn't send the actual request
+        let origin = request.headers().get("Origin").unwrap_or_default();
+        let method = request
+            .headers()
+            .get("Access-Control-Request-Method")
+            .unwrap_or_default()
+            .trim();
+
+        if self.origin_allowed(origin) && self.preflight_allowed(request, method) {
+            self.allow_origin(origin, &mut response);
+
+            let methods = join_list(self.methods.iter().map(Method::as_str));
+            response
+                .headers
+                .insert("Access-Control-Allow-Methods", methods);
+
+            if !self.headers.is_empty() {
+                response.headers.insert(
+                    "Access-Control-Allow-Headers",
+                    join_list(self.headers.iter()),
+                );
+            }
+
+            if let Some(max_age) = self.max_age {
+                response.headers.insert("Access-Control-Max-Age", max_age);
+            }
+        }
+
+        Some(response)
+    }
+
+    fn after(&mut self, request: &Request, response: &mut Response) {
+        if is_preflight(request) {
+            return;
+        }
+
+        if let Some(origin) = request.headers().get("Origin") {
+            if self.origin_allowed(origin) {
+                self.allow_origin(origin, response);
+            }
+        }
+    }
+}
+
+/// Whether the browser is asking for permission before the actual request
+fn is_preflight(request: &Request) -> bool {
+    *request.method() == Method::Options
+        && request.headers().contains_key("Origin")
+        && request
+            .headers()
+            .contains_key("Access-Control-Request-Method")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::request;
+    use super::*;
+
+    const ORIGIN: &str = "http://192.168.1.10:8080";
+
+    fn preflight(cors: &mut Cors, method: &str, headers: &str) -> Response {
+        let mut request = request(
+            Method::Options,
+            "/pins",
+            &[
+                ("Origin", ORIGIN),
+                ("Access-Control-Request-Method", method),
+                ("Access-Control-Request-Headers", headers),
+            ],
+        );
+
+        cors.before(&mut request).unwrap()
+    }
+
+    fn get(cors: &mut Cors, headers: &[(&str, &str)]) -> Response {
+        let mut request = request(Method::Get, "/pins", headers);
+        let mut response = ResponseBuilder::new(Status::OK)
+            .header("Vary", "Accept-Encoding")
+            .finalize();
+
+        assert!(cors.before(&mut request).is_none());
+        cors.after(&request, &mut response);
+        response
+    }
+
+    #[test]
+    fn answers_allowed_preflights() {
+        let mut cors = Cors::new()
+            .methods(&[Method::Get, Method::Post])
+            .header("Content-Type")
+            .max_age(600);
+
+        let response = preflight(&mut cors, "POST", "content-type");
+        let headers = &response.headers;
+
+        assert_eq!(response.status, Status::NoContent);
+        assert_eq!(headers.get("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            headers.get("Access-Control-Allow-Methods"),
+            Some("GET, POST")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        assert_eq!(headers.get("Access-Control-Max-Age"), Some("600"));
+        assert!(!headers.contains_key("Vary"));
+    }
+
+    #[test]
+    fn rejects_preflights_without_cors_headers() {
+        let mut cors = Cors::new().header("Content-Type");
+        let rejected = [
+            preflight(&mut cors, "DELETE", ""),
+            preflight(&mut cors, "POST", "Content-Type, X-Custom"),
+            preflight(&mut Cors::new().origin("http://example.com"), "GET", ""),
+        ];
+
+        for response in rejected.iter() {
+            assert_eq!(response.status, Status::NoContent);
+            assert_eq!(response.headers.len(), 0);
+        }
+    }
+
+    #[test]
+    fn passes_other_options_requests_on() {
+        let mut request = request(Method::Options, "/pins", &[("Origin", ORIGIN)]);
+
+        assert!(Cors::new().before(&mut request).is_none());
+    }
+
+    #[test]
+    fn allows_any_origin() {
+        let response = get(&mut Cors::new(), &[("Origin", ORIGIN)]);
+
+        let vary: Vec<&str> = response.headers.get_all("Vary").collect();
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(vary, ["Accept-Encoding"]);
+    }
+
+    #[test]
+    fn reflects_origins_with_credentials() {
+        let response = get(&mut Cors::new().credentials(true), &[("Origin", ORIGIN)]);
+        let headers = &response.headers;
+
+        let vary: Vec<&str> = headers.get_all("Vary").collect();
+        assert_eq!(headers.get("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(
+            headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(vary, ["Accept-Encoding", "Origin"]);
+    }
+
+    #[test]
+    fn only_allows_added_origins() {
+        let mut cors = Cors::new().origin(ORIGIN);
+
+        let allowed = get(&mut cors, &[("Origin", ORIGIN)]);
+        let other = get(&mut cors, &[("Origin", "http://example.com")]);
+        let same_origin = get(&mut cors, &[]);
+
+        assert_eq!(
+            allowed.headers.get("Access-Control-Allow-Origin"),
+            Some(ORIGIN)
+        );
+        assert!(allowed.headers.contains_token("Vary", "Origin"));
+        for response in [other, same_origin].iter() {
+            assert!(!response.headers.contains_key("Access-Control-Allow-Origin"));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use log::{debug, info};
+use smoltcp::dhcp::Dhcpv4Client;
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::Device;
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+
+// Big enough for a DHCP packet including all options we care about
+const DHCP_BUFFER_SIZE: usize = 900;
+
+/// Network configuration leased from a DHCP server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DhcpLease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+}
+
+/// DHCP client that applies its leases to the ethernet interface.
+///
+/// Renewing the lease is handled by the smoltcp client, a renewed lease is
+/// only applied again if it differs from the current one.
+pub struct Dhcp {
+    client: Dhcpv4Client,
+    lease: Option<DhcpLease>,
+}
+
+impl Dhcp {
+    pub fn new(sockets: &mut SocketSet<'static, 'static, 'static>, now: Instant) -> Dhcp {
+        let rx_buffer =
+            RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; DHCP_BUFFER_SIZE]);
+        let tx_buffer =
+            RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; DHCP_BUFFER_SIZE]);
+
+        Dhcp {
+            client: Dhcpv4Client::new(sockets, rx_buffer, tx_buffer, now),
+            lease: None,
+        }
+    }
+
+    pub fn lease(&self) -> Option<&DhcpLease> {
+        self.lease.as_ref()
+    }
+
+    pub fn poll<D: for<'d> Device<'d>>(
+        &mut self,
+        ethernet_interface: &mut EthernetInterface<'static, 'static, 'static, D>,
+        sockets: &mut SocketSet<'static, 'static, 'static>,
+        timestamp: Instant,
+    ) {
+        let config = match self.client.poll(ethernet_interface, sockets, timestamp) {
+            Ok(config) => config,
+            Err(e) => {
+                debug!("DHCP error: {}", e);
+                None
+            }
+        };
+
+        let lease = match config {
+            Some(config) => match config.address {
+                Some(address) => DhcpLease {
+                    address,
+                    router: config.router,
+                    dns_servers: config
+                        .dns_servers
+                        .iter()
+                        .filter_map(|dns_server| *dns_server)
+                        .collect(),
+                },
+                None => return,
+            },
+            None => return,
+        };
+
+        if self.lease.as_ref() == Some(&lease) {
+            return;
+        }
+
+        ethernet_interface.update_ip_addrs(|ip_addresses| {
+            if let Some(ip_address) = ip_addresses.iter_mut().next() {
+                *ip_address = IpCidr::Ipv4(lease.address);
+            }
+        });
+
+        match lease.router {
+            Some(router) => {
+                if let Err(e) = ethernet_interface
+                    .routes_mut()
+                    .add_default_ipv4_route(router)
+                {
+                    debug!("Could not set default route: {}", e);
+                }
+            }
+            // The previous router may not be reachable from the new address
+            None => ethernet_interface.routes_mut().update(|routes| {
+                routes.remove(&IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0));
+            }),
+        }
+
+        info!("DHCP lease: {} via {:?}", lease.address, lease.router);
+        info!("DNS servers: {:?}", lease.dns_servers);
+
+        self.lease = Some(lease);
+    }
+}
@@ -0,0 +1,24 @@
+use core::fmt;
+
+/// Errors that occured while serving a connection.
+///
+/// The affected connection is aborted, all other connections are unaffected.
+#[derive(Clone, Debug)]
+pub enum Error {
+    Listen(smoltcp::Error),
+    Receive(smoltcp::Error),
+    Send(smoltcp::Error),
+    /// The client stopped reading what is sent to it
+    Stalled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Listen(e) => write!(f, "could not listen: {}", e),
+            Error::Receive(e) => write!(f, "could not receive: {}", e),
+            Error::Send(e) => write!(f, "could not send: {}", e),
+            Error::Stalled => write!(f, "client stopped reading"),
+        }
+    }
+}
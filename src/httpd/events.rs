@@ -0,0 +1,203 @@
+use alloc::{
+    collections::VecDeque,
+    format,
+    string::{String, ToString},
+};
+use smoltcp::socket::TcpSocket;
+use smoltcp::time::{Duration, Instant};
+
+use super::cache::CacheControl;
+use super::error::Error;
+use super::method::Method;
+use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::send_buffer::SendBuffer;
+use super::status::Status;
+
+// Proxies and browsers may drop connections that are silent for too long
+const KEEPALIVE_INTERVAL_SECS: u64 = 15;
+
+// Events kept for clients that reconnect
+const BUFFERED_EVENTS: usize = 16;
+
+/// An event for Server-Sent Events streams, see `HTTPD::publish`.
+#[derive(Clone, Debug)]
+pub struct Event {
+    name: Option<String>,
+    data: String,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Event {
+        Event {
+            name: None,
+            data: data.to_string(),
+        }
+    }
+
+    /// Sets the event type, which browsers dispatch to listeners of that
+    /// name instead of `message`.
+    pub fn name(mut self, name: &str) -> Self {
+        // Line breaks would end the field early
+        self.name = Some(name.replace(|c: char| c == '\r' || c == '\n', ""));
+        self
+    }
+
+    /// Serializes the event in the `text/event-stream` format
+    fn serialize(&self, id: u64) -> String {
+        let mut serialized = String::new();
+
+        if let Some(name) = &self.name {
+            serialized.push_str(&format!("event: {}\n", name));
+        }
+        serialized.push_str(&format!("id: {}\n", id));
+
+        // Every line of the data gets its own field
+        for line in self.data.split('\n') {
+            serialized.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        serialized.push('\n');
+
+        serialized
+    }
+}
+
+/// The most recent events, so that reconnecting clients can resume.
+pub(super) struct EventBuffer {
+    events: VecDeque<(u64, Event)>,
+    next_id: u64,
+}
+
+impl EventBuffer {
+    pub fn new() -> EventBuffer {
+        EventBuffer {
+            events: VecDeque::with_capacity(BUFFERED_EVENTS),
+            next_id: 1,
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.events.len() == BUFFERED_EVENTS {
+            self.events.pop_front();
+        }
+
+        self.events.push_back((self.next_id, event));
+        self.next_id += 1;
+    }
+
+    /// Id of the latest event, 0 before the first one
+    fn last_id(&self) -> u64 {
+        self.next_id - 1
+    }
+}
+
+/// Whether the response starts an event stream
+pub(super) fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers
+        .content_type()
+        .map(|content_type| content_type.starts_with("text/event-stream"))
+        .unwrap_or(false)
+}
+
+/// A connection that receives Server-Sent Events.
+#[derive(Debug)]
+pub struct EventStream {
+    // Id of the last event that was sent
+    last_id: Option<u64>,
+    output: SendBuffer,
+    chunked: bool,
+    last_write: Instant,
+}
+
+impl EventStream {
+    /// Starts an event stream, for use in a route.
+    ///
+    /// The connection stays open after the response and gets all events
+    /// published with `HTTPD::publish` from then on. Clients reconnecting
+    /// with `Last-Event-ID` get the events they missed first, as long as
+    /// they are still buffered.
+    pub fn response() -> Response {
+        ResponseBuilder::new(Status::OK)
+            .header("Content-Type", "text/event-stream")
+            .cache_control(CacheControl::NoStore)
+            .finalize()
+    }
+
+    /// The event stream for a response, if it is one
+    pub(super) fn open(request: &Request, response: &Response) -> Option<EventStream> {
+        // Events are the whole body, anything the route wrote would break the
+        // framing
+        if *request.method() != Method::Get
+            || response.status != Status::OK
+            || !is_event_stream(response)
+            || response.body.length() != Some(0)
+        {
+            return None;
+        }
+
+        Some(EventStream {
+            last_id: request
+                .headers()
+                .get("Last-Event-ID")
+                .and_then(|id| id.trim().parse().ok()),
+            output: SendBuffer::new(),
+            chunked: false,
+            last_write: Instant::from_millis(0),
+        })
+    }
+
+    /// Called once the response head is sent
+    pub(super) fn start(&mut self, chunked: bool, timestamp: Instant) {
+        self.chunked = chunked;
+        self.last_write = timestamp;
+    }
+
+    /// Queues all buffered events that weren't sent yet, or a comment if
+    /// there were none for a while.
+    pub(super) fn catch_up(&mut self, events: &EventBuffer, timestamp: Instant) {
+        if !self.output.has_room() {
+            return;
+        }
+
+        // New clients only get events published from now on, ids from before
+        // a restart of the server can't be resumed from either
+        let last_id = match self.last_id {
+            Some(last_id) if last_id <= events.last_id() => last_id,
+            _ => events.last_id(),
+        };
+        self.last_id = Some(last_id);
+
+        let mut serialized = String::new();
+        for (id, event) in events.events.iter().filter(|(id, _)| *id > last_id) {
+            serialized.push_str(&event.serialize(*id));
+            self.last_id = Some(*id);
+        }
+
+        if serialized.is_empty()
+            && timestamp - self.last_write > Duration::from_secs(KEEPALIVE_INTERVAL_SECS)
+        {
+            serialized.push_str(": keepalive\n\n");
+        }
+
+        if serialized.is_empty() {
+            return;
+        }
+
+        self.last_write = timestamp;
+
+        if self.chunked {
+            self.output
+                .extend(format!("{:x}\r\n", serialized.len()).as_bytes());
+            self.output.extend(serialized.as_bytes());
+            self.output.extend(b"\r\n");
+        } else {
+            self.output.extend(serialized.as_bytes());
+        }
+    }
+
+    /// Sends as much of the queued events as possible.
+    pub(super) fn send_output(&mut self, socket: &mut TcpSocket) -> Result<usize, Error> {
+        self.output.send(socket)
+    }
+}
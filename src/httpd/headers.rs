@@ -0,0 +1,136 @@
+use alloc::{
+    string::{String, ToString},
+    vec::{self, Vec},
+};
+
+/// Header fields of a request or response.
+///
+/// Names are compared case-insensitively but sent as they were added. A name
+/// can have multiple values, which keep the order they were added in.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap { fields: vec![] }
+    }
+
+    /// The first value of the field `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the field `name`, in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets the field `name`, replacing all of its previous values.
+    pub fn insert<K: ToString, V: ToString>(&mut self, name: K, value: V) {
+        let name = name.to_string();
+        self.remove(&name);
+        self.fields.push((name, value.to_string()));
+    }
+
+    /// Adds another value to the field `name`, keeping previous values.
+    pub fn append<K: ToString, V: ToString>(&mut self, name: K, value: V) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Removes all values of the field `name`, returns whether there were any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.fields.len();
+        self.fields
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.fields.len() != len
+    }
+
+    #[allow(dead_code)]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Whether any value of the comma-separated list `name` is `token`,
+    /// ignoring case (e.g. `Connection: keep-alive, Upgrade`)
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// `Content-Length`, if it is present, valid and not ambiguous
+    pub fn content_length(&self) -> Option<usize> {
+        let mut lengths = self
+            .get_all("Content-Length")
+            .map(|length| length.trim().parse::<usize>().ok());
+        let first = lengths.next()??;
+
+        if lengths.all(|length| length == Some(first)) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_content_length(&mut self, length: usize) {
+        self.insert("Content-Length", length);
+    }
+
+    /// `Content-Type`, including any parameters like `charset`
+    pub fn content_type(&self) -> Option<&str> {
+        self.get("Content-Type")
+    }
+
+    pub fn set_content_type(&mut self, content_type: &str) {
+        self.insert("Content-Type", content_type);
+    }
+
+    /// Whether the `Connection` field contains the option `option`
+    pub fn connection(&self, option: &str) -> bool {
+        self.contains_token("Connection", option)
+    }
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = (String, String);
+    type IntoIter = vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter()
+    }
+}
+
+/// Joins the items of a comma-separated header value
+pub(super) fn join_list<S: AsRef<str>, I: Iterator<Item = S>>(items: I) -> String {
+    let mut list = String::new();
+    for item in items {
+        if !list.is_empty() {
+            list.push_str(", ");
+        }
+        list.push_str(item.as_ref());
+    }
+    list
+}
@@ -0,0 +1,40 @@
+use smoltcp::time::Duration;
+
+/// Limits on what a client may send to the server.
+///
+/// Requests exceeding a limit are answered with an error status and the
+/// connection is closed.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum length of the request line (`414 URI Too Long`)
+    pub max_request_line_length: usize,
+    /// Maximum number of header fields, and of trailer fields of chunked
+    /// bodies (`431 Request Header Fields Too Large`)
+    pub max_header_count: usize,
+    /// Maximum size of the whole request head, and of the trailer section of
+    /// chunked bodies (`431 Request Header Fields Too Large`)
+    pub max_head_size: usize,
+    /// Maximum size of a request body, chunked or not (`413 Payload Too Large`),
+    /// and of WebSocket messages
+    pub max_body_size: usize,
+    /// Time a connection may go without receiving anything while a request
+    /// is expected. Partially received requests get a `408 Request Timeout`,
+    /// idle WebSockets are pinged instead. Also the time a client may take to
+    /// read any part of a response, before the connection is aborted.
+    pub idle_timeout: Duration,
+    /// Time the client may take to send a complete request head (`408 Request Timeout`)
+    pub header_read_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_request_line_length: 1024,
+            max_header_count: 32,
+            max_head_size: 4096,
+            max_body_size: 16 * 1024,
+            idle_timeout: Duration::from_secs(10),
+            header_read_timeout: Duration::from_secs(5),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use log::info;
+
+use super::cache;
+use super::events;
+use super::range;
+use super::request::Request;
+use super::response::Response;
+
+/// Hooks that run around the routes callback for every request.
+///
+/// Middlewares run in the order they were added to the server: `before` hooks
+/// from first to last, then the routes callback, then `after` hooks from last
+/// to first. Conditional and range requests are answered before the `after`
+/// hooks, so they see the response as it is sent.
+pub trait Middleware {
+    /// Called before the request is routed, may modify the request.
+    ///
+    /// Returning a response skips the routes callback and all middlewares
+    /// added later. The `after` hooks of this and all earlier middlewares are
+    /// still called.
+    fn before(&mut self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Called with the response before it is sent, may modify the response.
+    fn after(&mut self, _request: &Request, _response: &mut Response) {}
+}
+
+/// Runs the request through `middlewares` and the routes callback.
+pub(super) fn handle<F: FnMut(&Request, &Vec<u8>) -> Response>(
+    middlewares: &mut [Box<dyn Middleware>],
+    request: &mut Request,
+    body: &Vec<u8>,
+    routes_callback: &mut F,
+) -> Response {
+    let mut short_circuit = None;
+    let mut called = 0;
+
+    for middleware in middlewares.iter_mut() {
+        called += 1;
+        short_circuit = middleware.before(request);
+
+        if short_circuit.is_some() {
+            break;
+        }
+    }
+
+    let mut response = match short_circuit {
+        Some(response) => response,
+        None => routes_callback(request, body),
+    };
+
+    // Streams have no representation that a client could have cached or
+    // request parts of
+    if !events::is_event_stream(&response) {
+        cache::evaluate_preconditions(request, &mut response);
+        range::apply_range(request, &mut response);
+    }
+
+    for middleware in middlewares[..called].iter_mut().rev() {
+        middleware.after(request, &mut response);
+    }
+
+    response
+}
+
+/// Sets the `Server` header, unless the route already did.
+pub struct ServerHeader {
+    value: String,
+}
+
+impl ServerHeader {
+    pub fn new(value: &str) -> ServerHeader {
+        ServerHeader {
+            value: value.to_string(),
+        }
+    }
+}
+
+impl Middleware for ServerHeader {
+    fn after(&mut self, _request: &Request, response: &mut Response) {
+        if !response.headers.contains_key("Server") {
+            response.headers.insert("Server", &self.value);
+        }
+    }
+}
+
+/// Logs every request with the status of its response.
+///
+/// Add it first to log the response as it is sent.
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn after(&mut self, request: &Request, response: &mut Response) {
+        let request_text = format!("{} {}", request.method(), request.path());
+        let (status_num, status_text) = response.status.numerical_and_text();
+        let response_text = format!("[{} {}]", status_num, status_text);
+        let padding = 50usize.saturating_sub(request_text.len() + response_text.len());
+
+        info!("{}{}{}", request_text, " ".repeat(padding), response_text);
+    }
+}
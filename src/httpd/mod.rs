@@ -1,15 +1,21 @@
 //! HTTP Server Module
 
-use alloc::{borrow::ToOwned, collections::BTreeMap, format, string::String, vec::Vec};
-use log::{debug, trace};
-use smoltcp::iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache};
-use smoltcp::socket::{SocketHandle, SocketSet, TcpSocket, TcpSocketBuffer};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use log::debug;
+use smoltcp::iface::{
+    EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes as IpRoutes,
+};
+use smoltcp::phy::Device;
+use smoltcp::socket::{SocketSet, TcpSocket, TcpSocketBuffer};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr};
-use stm32f7::stm32f7x6::{ETHERNET_DMA, ETHERNET_MAC, RCC, SYSCFG};
-use stm32f7_discovery::ethernet::{self, PhyError};
-use stm32f7_discovery::{self, system_clock};
-
+use smoltcp::wire::EthernetAddress;
+
+mod method;
+pub use self::method::Method;
+mod body;
+pub use self::body::{Body, BodyStream};
+mod headers;
+pub use self::headers::HeaderMap;
 mod request;
 pub use self::request::Request;
 mod response;
@@ -18,280 +24,202 @@ mod status;
 pub use self::status::Status;
 mod routes;
 pub use self::routes::Routes;
-
+mod middleware;
+pub use self::middleware::{AccessLog, Middleware, ServerHeader};
+mod cors;
+pub use self::cors::Cors;
+mod cache;
+pub use self::cache::{etag, CacheControl};
+mod static_files;
+pub use self::static_files::{mime_type, StaticFile, StaticFiles};
+mod websocket;
+pub use self::websocket::{Message, WebSocket};
+mod events;
+use self::events::EventBuffer;
+pub use self::events::{Event, EventStream};
+
+#[cfg(feature = "board")]
+mod board;
+mod chunked;
+mod connection;
+use self::connection::Connection;
+mod error;
+pub use self::error::Error;
+mod limits;
+pub use self::limits::Limits;
+mod dhcp;
+use self::dhcp::Dhcp;
+pub use self::dhcp::DhcpLease;
+mod network;
+pub use self::network::NetworkConfig;
 mod parser;
-use parser::{HTTPParser, ParseError};
+mod range;
+mod send_buffer;
+mod sha1;
+mod url;
 
-pub struct HTTPD<F> {
-    ethernet_interface: EthernetInterface<'static, 'static, 'static, ethernet::EthernetDevice>,
+#[cfg(test)]
+mod tests;
+
+pub struct HTTPD<D: for<'d> Device<'d>, F> {
+    ethernet_interface: EthernetInterface<'static, 'static, 'static, D>,
     sockets: SocketSet<'static, 'static, 'static>,
-    tcp_handle: SocketHandle,
+    connections: Vec<Connection>,
     port: u16,
-    connected: bool,
-    input_buffer: Vec<u8>,
-    request_state: RequestState,
+    clock: fn() -> Instant,
+    limits: Limits,
+    dhcp: Option<Dhcp>,
+    middlewares: Vec<Box<dyn Middleware>>,
+    events: EventBuffer,
     routes_callback: F,
 }
 
-impl<F: FnMut(&Request, &Vec<u8>) -> Response> HTTPD<F> {
-    pub fn new(
-        rcc: &mut RCC,
-        syscfg: &mut SYSCFG,
-        ethernet_mac: &mut ETHERNET_MAC,
-        ethernet_dma: ETHERNET_DMA,
+impl<D: for<'d> Device<'d>, F: FnMut(&Request, &Vec<u8>) -> Response> HTTPD<D, F> {
+    /// Creates a server on top of any smoltcp device.
+    ///
+    /// `clock` is called on every poll to get the current time. On the board,
+    /// use `HTTPD::new` instead, which sets up the ethernet device and clock.
+    pub fn with_device(
+        device: D,
         ethernet_addr: EthernetAddress,
-        ip_addr: IpAddress,
+        network_config: NetworkConfig,
         port: u16,
+        max_connections: usize,
+        clock: fn() -> Instant,
         routes_callback: F,
-    ) -> Result<Self, PhyError> {
-        ethernet::EthernetDevice::new(
-            Default::default(),
-            Default::default(),
-            rcc,
-            syscfg,
-            ethernet_mac,
-            ethernet_dma,
-            ethernet_addr,
-        )
-        .map(|ethernet_device| {
-            let ip_addresses = [IpCidr::new(ip_addr, 24)];
-
-            let tcp_receive_buffer = TcpSocketBuffer::new(vec![0; ethernet::MTU]);
-            let tcp_send_buffer = TcpSocketBuffer::new(vec![0; ethernet::MTU]);
-            let tcp_socket = TcpSocket::new(tcp_receive_buffer, tcp_send_buffer);
-
-            // ARP cache of MAC address => IP address mappings
-            let neighbor_cache = NeighborCache::new(BTreeMap::new());
-
-            let ethernet_interface = EthernetInterfaceBuilder::new(ethernet_device)
-                .ethernet_addr(ethernet_addr)
-                .neighbor_cache(neighbor_cache)
-                .ip_addrs(ip_addresses)
-                .finalize();
-
-            let mut sockets = SocketSet::new(vec![]);
-            let tcp_handle = sockets.add(tcp_socket);
-
-            HTTPD {
-                ethernet_interface,
-                sockets,
-                tcp_handle,
-                port,
-                connected: false,
-                input_buffer: vec![],
-                request_state: RequestState::Wait,
-                routes_callback,
-            }
-        })
-    }
-
-    pub fn poll(&mut self) {
-        let timestamp = Instant::from_millis(system_clock::ms() as i64);
-
-        match self.ethernet_interface.poll(&mut self.sockets, timestamp) {
-            Ok(_) => {}
-            Err(e) => {
-                debug!("polling error: {}", e);
-            }
-        }
-
-        match self.poll_socket() {
-            PollStatus::Established => self.request_init(),
-            PollStatus::Received(data) => self.request_receive(data),
-            PollStatus::Closed => self.request_close(),
-            PollStatus::Inactive => (),
+    ) -> Self {
+        let ip_addresses = [network_config.ip_cidr()];
+
+        // Socket buffers can hold exactly one frame
+        let buffer_size = device.capabilities().max_transmission_unit;
+
+        // ARP cache of MAC address => IP address mappings
+        let neighbor_cache = NeighborCache::new(BTreeMap::new());
+
+        // Routing table, DHCP may replace the default gateway later on
+        let ip_routes = IpRoutes::new(network_config.route_map());
+
+        let ethernet_interface = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(ethernet_addr)
+            .neighbor_cache(neighbor_cache)
+            .ip_addrs(ip_addresses)
+            .routes(ip_routes)
+            .finalize();
+
+        let mut sockets = SocketSet::new(vec![]);
+
+        // One listening socket per connection that can be served at once
+        let connections = (0..max_connections)
+            .map(|_| {
+                let tcp_receive_buffer = TcpSocketBuffer::new(vec![0; buffer_size]);
+                let tcp_send_buffer = TcpSocketBuffer::new(vec![0; buffer_size]);
+                let tcp_socket = TcpSocket::new(tcp_receive_buffer, tcp_send_buffer);
+
+                Connection::new(sockets.add(tcp_socket))
+            })
+            .collect();
+
+        HTTPD {
+            ethernet_interface,
+            sockets,
+            connections,
+            port,
+            clock,
+            limits: Limits::default(),
+            dhcp: None,
+            middlewares: vec![],
+            events: EventBuffer::new(),
+            routes_callback,
         }
     }
 
-    fn request_init(&mut self) {
-        debug!("Connection opened");
-        self.input_buffer = vec![];
-        self.request_state = RequestState::ReadHead;
+    /// Replaces the default limits for requests.
+    #[allow(dead_code)]
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    fn request_receive(&mut self, chunk: Vec<u8>) {
-        let read = chunk.len();
-        debug!("Received {} bytes", read);
-
-        self.input_buffer.extend(chunk);
-
-        match &self.request_state {
-            RequestState::ReadHead => self.read_head(),
-            RequestState::ReadBody(request, bytes_to_read) => {
-                self.read_body(request.clone(), *bytes_to_read, read)
-            }
-            state => trace!("Can't receive in state {:?}", state),
-        }
+    /// Adds a middleware that runs after all middlewares added before.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middlewares.push(Box::new(middleware));
     }
 
-    fn read_body(&mut self, request: Request, bytes_to_read: usize, read: usize) {
-        self.request_state = if read >= bytes_to_read {
-            self.input_buffer
-                .truncate(self.input_buffer.len() - (read - bytes_to_read));
-            RequestState::RequestRead(request, self.input_buffer.clone())
-        } else {
-            RequestState::ReadBody(request, bytes_to_read - read)
+    /// Starts a DHCP client and uses its leases instead of the static address.
+    ///
+    /// The static address stays in use until the first lease is acquired.
+    pub fn enable_dhcp(&mut self) {
+        if self.dhcp.is_none() {
+            self.dhcp = Some(Dhcp::new(&mut self.sockets, (self.clock)()));
         }
     }
 
-    fn read_head(&mut self) {
-        // TODO: do this better (i'm sure it's possible)
-        // This expression basically copies the buffer
-        //                      v----------------------------v
-        match String::from_utf8(self.input_buffer[..].to_vec()) {
-            Ok(input_string) => {
-                let mut parser = HTTPParser::new(&input_string);
-
-                match parser.parse_head() {
-                    Ok(request_head) => {
-                        debug!("Request head parsed.");
-
-                        let content_length_res =
-                            request_head.headers().get("content-length").and_then(
-                                |content_length_field| content_length_field.parse::<usize>().ok(),
-                            );
-
-                        match content_length_res {
-                            Some(content_length) => {
-                                self.input_buffer = parser.source.as_bytes().to_vec();
-                                self.read_body(
-                                    request_head,
-                                    content_length,
-                                    self.input_buffer.len(),
-                                );
-                            }
-                            None => {
-                                self.request_state = RequestState::RequestRead(request_head, vec![])
-                            }
-                        }
-                    }
-                    Err(ParseError::NotEnoughInput) => {
-                        trace!("Request header incomplete");
-                    }
-                    Err(ParseError::Fatal) => {
-                        trace!("Could not parse request header");
-                        self.request_state = RequestState::ParseError;
-                    }
-                }
-            }
-            Err(_e) => {
-                trace!("Request header is not UTF-8");
-                self.request_state = RequestState::ParseError;
-            }
-        }
+    /// The current DHCP lease, if DHCP is enabled and a lease was acquired.
+    pub fn dhcp_lease(&self) -> Option<&DhcpLease> {
+        self.dhcp.as_ref().and_then(|dhcp| dhcp.lease())
     }
 
-    fn request_close(&mut self) {
-        debug!("Connection closed");
-        self.request_state = RequestState::Wait;
+    /// All connections that were upgraded with `WebSocket::handshake`, to
+    /// receive and send messages.
+    pub fn websockets(&mut self) -> impl Iterator<Item = &mut WebSocket> {
+        self.connections
+            .iter_mut()
+            .filter_map(|connection| connection.websocket_mut())
     }
 
-    fn want_receive(&self) -> bool {
-        match self.request_state {
-            RequestState::ReadHead | RequestState::ReadBody(_, _) => true,
-            _ => false,
-        }
+    /// Sends an event to all clients of an `EventStream`.
+    ///
+    /// The latest events are buffered, so that clients reconnecting with
+    /// `Last-Event-ID` don't miss any.
+    pub fn publish(&mut self, event: Event) {
+        self.events.push(event);
     }
 
-    fn poll_socket(&mut self) -> PollStatus {
-        // Needed below, but 'let mut socket' keeps a mut ref to self
-        let want_receive = self.want_receive();
-
-        let mut socket = self.sockets.get::<TcpSocket>(self.tcp_handle);
+    /// Handles incoming packets and serves all connections.
+    ///
+    /// A connection that fails is aborted and its error returned, after all
+    /// other connections have been served.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        let timestamp = (self.clock)();
 
-        let old_connection_status = self.connected;
-        self.connected = socket.is_active();
-
-        if old_connection_status != self.connected {
-            return if self.connected {
-                PollStatus::Established
-            } else {
-                PollStatus::Closed
-            };
+        match self.ethernet_interface.poll(&mut self.sockets, timestamp) {
+            Ok(_) => {}
+            Err(e) => {
+                debug!("polling error: {}", e);
+            }
         }
 
-        if !socket.is_open() {
-            socket.listen(self.port).expect("Could not listen");
-            debug!("Listening...");
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            dhcp.poll(&mut self.ethernet_interface, &mut self.sockets, timestamp);
         }
 
-        if socket.may_recv() && want_receive {
-            let data = socket
-                .recv(|recv_buffer| (recv_buffer.len(), recv_buffer.to_owned()))
-                .unwrap();
+        let middlewares = &mut self.middlewares;
+        let routes_callback = &mut self.routes_callback;
+        let mut handler = |request: &mut Request, body: &Vec<u8>| {
+            middleware::handle(middlewares, request, body, routes_callback)
+        };
 
-            if data.len() > 0 {
-                return PollStatus::Received(data);
-            }
-        } else if socket.may_send() {
-            match &self.request_state {
-                RequestState::RequestRead(request, body) => {
-                    debug!("Request head:");
-                    debug!("{:?}", request);
-                    debug!("Body:");
-                    debug!("{:?}", body);
-
-                    let response = (self.routes_callback)(request, body);
-                    let (status_num, status_text) = response.status.numerical_and_text();
-
-                    socket
-                        .send_slice(
-                            format!("HTTP/1.1 {} {}\r\n", status_num, status_text).as_bytes(),
-                        )
-                        .expect("Could not send head line");
-
-                    trace!("Sending headers: {:?}", response.headers);
-
-                    for (key, value) in response.headers {
-                        socket
-                            .send_slice(format!("{}: {}\r\n", key, value).as_bytes())
-                            .expect("Could not send header");
-                    }
-
-                    socket
-                        .send_slice("\r\n".as_bytes())
-                        .expect("Could not send end-of-header");
+        let mut result = Ok(());
 
-                    debug!("Sending body");
-                    self.request_state = RequestState::SendBody(response.body);
-                }
-                RequestState::SendBody(body) => {
-                    trace!("{} bytes remaining", body.len());
-
-                    let bytes_sent = socket.send_slice(&body).expect("Could not send body");
-                    trace!("{} bytes sent", bytes_sent);
+        for connection in self.connections.iter_mut() {
+            // Also keeps idle streams alive with comments
+            if let Some(event_stream) = connection.event_stream_mut() {
+                event_stream.catch_up(&self.events, timestamp);
+            }
 
-                    if bytes_sent < body.len() {
-                        self.request_state =
-                            RequestState::SendBody(body.split_at(bytes_sent).1.to_vec());
-                    } else {
-                        socket.close();
-                    }
-                }
-                _ => {
-                    debug!("Request not read");
-                    socket.close();
-                }
+            let mut socket = self.sockets.get::<TcpSocket>(connection.tcp_handle());
+            let connection_result = connection.poll(
+                &mut socket,
+                self.port,
+                timestamp,
+                &self.limits,
+                &mut handler,
+            );
+
+            if result.is_ok() {
+                result = connection_result;
             }
         }
 
-        PollStatus::Inactive
+        result
     }
 }
-
-enum PollStatus {
-    Established,
-    Received(Vec<u8>),
-    Closed,
-    Inactive,
-}
-
-#[derive(Clone, Debug)]
-enum RequestState {
-    Wait,
-    ReadHead,
-    ReadBody(Request, usize),
-    RequestRead(Request, Vec<u8>),
-    SendBody(Vec<u8>),
-    ParseError,
-}
@@ -0,0 +1,65 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use smoltcp::iface::Route;
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address};
+
+/// Static network configuration of the server's interface.
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    pub ip_addr: IpAddress,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Address>,
+    pub routes: Vec<(IpCidr, IpAddress)>,
+}
+
+impl NetworkConfig {
+    pub fn new(ip_addr: IpAddress, prefix_len: u8) -> NetworkConfig {
+        NetworkConfig {
+            ip_addr,
+            prefix_len,
+            gateway: None,
+            routes: vec![],
+        }
+    }
+
+    /// Sets the router for all addresses outside of the local network.
+    pub fn gateway(mut self, gateway: Ipv4Address) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Adds a route to `cidr` via the router at `via_router`.
+    #[allow(dead_code)]
+    pub fn route(mut self, cidr: IpCidr, via_router: IpAddress) -> Self {
+        self.routes.push((cidr, via_router));
+        self
+    }
+
+    pub fn ip_cidr(&self) -> IpCidr {
+        IpCidr::new(self.ip_addr, self.prefix_len)
+    }
+
+    /// Routing table for the interface, including the default route.
+    pub fn route_map(&self) -> BTreeMap<IpCidr, Route> {
+        let mut route_map = BTreeMap::new();
+
+        if let Some(gateway) = self.gateway {
+            route_map.insert(
+                IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0),
+                Route::new_ipv4_gateway(gateway),
+            );
+        }
+
+        for (cidr, via_router) in self.routes.iter() {
+            route_map.insert(
+                *cidr,
+                Route {
+                    via_router: *via_router,
+                    preferred_until: None,
+                    expires_at: None,
+                },
+            );
+        }
+
+        route_map
+    }
+}
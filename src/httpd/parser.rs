@@ -1,160 +1,420 @@
-use alloc::{
-    borrow::ToOwned,
-    collections::BTreeMap,
-    string::{String, ToString},
-};
+use alloc::string::{String, ToString};
+use core::{mem, ops::Range, str};
 
+use super::headers::HeaderMap;
+use super::limits::Limits;
+use super::method::{is_token_char, Method};
 use super::request::Request;
+use super::status::Status;
 
+const SUPPORTED_VERSIONS: [&[u8]; 2] = [b"HTTP/1.0", b"HTTP/1.1"];
+
+/// Incremental parser for request heads.
+///
+/// The parser works directly on the connection's input buffer and remembers
+/// how far it got, so every byte is only looked at once, no matter how many
+/// parts the head arrives in. Nothing is copied until the head is complete.
+#[derive(Debug)]
 pub struct HTTPParser {
-    pub source: String,
-    method: Option<String>,
-    path: Option<String>,
-    version: Option<String>,
-    headers: BTreeMap<String, String>,
+    // Start of the line that is parsed next
+    position: usize,
+    // Everything before this offset has been searched for line ends
+    scanned: usize,
+    state: ParseState,
+    method: Range<usize>,
+    target: Range<usize>,
+    version: Range<usize>,
+    headers: HeaderMap,
+    header_count: usize,
 }
 
 #[derive(Debug)]
+enum ParseState {
+    RequestLine,
+    Headers,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ParseError {
     NotEnoughInput,
-    Fatal,
+    Malformed,
+    URITooLong,
+    HeadTooLarge,
+    UnsupportedVersion,
+    BodyTooLarge,
+    UnknownExpectation,
+    UnsupportedTransferCoding,
+    Timeout,
+}
+
+impl ParseError {
+    /// Status to answer the request with
+    pub fn status(&self) -> Status {
+        match self {
+            ParseError::NotEnoughInput | ParseError::Malformed => Status::BadRequest,
+            ParseError::URITooLong => Status::URITooLong,
+            ParseError::HeadTooLarge => Status::RequestHeaderFieldsTooLarge,
+            ParseError::UnsupportedVersion => Status::HTTPVersionNotSupported,
+            ParseError::BodyTooLarge => Status::PayloadTooLarge,
+            ParseError::UnknownExpectation => Status::ExpectationFailed,
+            ParseError::UnsupportedTransferCoding => Status::NotImplemented,
+            ParseError::Timeout => Status::RequestTimeout,
+        }
+    }
 }
 
 impl HTTPParser {
-    pub fn new(source: &str) -> HTTPParser {
+    pub fn new() -> HTTPParser {
         HTTPParser {
-            source: source.to_string(),
-            method: None,
-            path: None,
-            version: None,
-            headers: BTreeMap::new(),
+            position: 0,
+            scanned: 0,
+            state: ParseState::RequestLine,
+            method: 0..0,
+            target: 0..0,
+            version: 0..0,
+            headers: HeaderMap::new(),
+            header_count: 0,
         }
     }
 
-    pub fn parse_head(&mut self) -> Result<Request, ParseError> {
-        self.parse_method()
-            .and_then(|_| self.expect(" "))
-            .and_then(|_| self.parse_path())
-            .and_then(|_| self.expect(" "))
-            .and_then(|_| self.parse_version())
-            .and_then(|_| self.expect("\r\n"))
-            .and_then(|_| self.parse_headers())
-            .and_then(|_| self.expect("\r\n"))
-            .and_then(|_| {
-                Ok(Request::new(
-                    self.method.to_owned().unwrap(),
-                    self.path.to_owned().unwrap(),
-                    self.version.to_owned().unwrap(),
-                    self.headers.to_owned(),
-                ))
-            })
-    }
-
-    fn parse_method(&mut self) -> Result<String, ParseError> {
-        let allowed_methods = ["GET", "POST"];
-        let min_method_length = allowed_methods
-            .iter()
-            .map(|method| method.len())
-            .min()
-            .unwrap();
-
-        if self.source.len() < min_method_length {
-            return Err(ParseError::NotEnoughInput);
-        }
+    /// Continues parsing the head at the start of `input`.
+    ///
+    /// `input` has to start with the same bytes as on the previous call. On
+    /// success, returns the request and the length of its head.
+    pub fn parse_head(
+        &mut self,
+        input: &[u8],
+        limits: &Limits,
+    ) -> Result<(Request, usize), ParseError> {
+        loop {
+            let line_end = match self.find_line_end(input) {
+                Some(line_end) => line_end,
+                None => return Err(self.check_incomplete(input, limits)),
+            };
 
-        for method in allowed_methods.iter() {
-            if self.source.starts_with(method) {
-                return self.expect(method).map(|method| {
-                    self.method = Some(method.to_owned());
-                    method
-                });
+            if line_end + 2 > limits.max_head_size {
+                return Err(ParseError::HeadTooLarge);
             }
-        }
 
-        Err(ParseError::Fatal)
-    }
+            let line = self.position..line_end;
+            self.position = line_end + 2;
+
+            match self.state {
+                // Robust servers skip empty lines before a request (RFC 7230, 3.5)
+                ParseState::RequestLine if line.start == line.end => {}
+                ParseState::RequestLine => {
+                    if line.end - line.start > limits.max_request_line_length {
+                        return Err(ParseError::URITooLong);
+                    }
+
+                    self.parse_request_line(input, line)?;
+                    self.state = ParseState::Headers;
+                }
+                ParseState::Headers if line.start == line.end => {
+                    return Ok((self.request(input)?, self.position));
+                }
+                ParseState::Headers => {
+                    self.header_count += 1;
+
+                    if self.header_count > limits.max_header_count {
+                        return Err(ParseError::HeadTooLarge);
+                    }
 
-    fn parse_path(&mut self) -> Result<String, ParseError> {
-        self.read_until(" ").map(|path| {
-            self.path = Some(path.to_owned());
-            path
-        })
+                    self.parse_header(&input[line])?;
+                }
+            }
+        }
     }
 
-    fn parse_version(&mut self) -> Result<String, ParseError> {
-        self.read_until("\r\n").map(|version| {
-            self.version = Some(version.to_owned());
-            version
-        })
+    /// Returns the offset of the next CRLF, if there is one yet.
+    fn find_line_end(&mut self, input: &[u8]) -> Option<usize> {
+        // The CR may have been the last byte seen on the previous call
+        let start = self.position.max(self.scanned.saturating_sub(1));
+
+        match input[start..]
+            .windows(2)
+            .position(|window| window == b"\r\n")
+        {
+            Some(offset) => Some(start + offset),
+            None => {
+                self.scanned = input.len();
+                None
+            }
+        }
     }
 
-    fn parse_headers(&mut self) -> Result<(), ParseError> {
-        loop {
-            if self.source.starts_with("\r\n") {
-                return Ok(());
+    /// Finds out whether an incomplete head can still become a valid one.
+    fn check_incomplete(&self, input: &[u8], limits: &Limits) -> ParseError {
+        if input.len() > limits.max_head_size {
+            return ParseError::HeadTooLarge;
+        }
+
+        if let ParseState::RequestLine = self.state {
+            let partial_line = &input[self.position..];
+            let method = partial_line
+                .split(|byte| *byte == b' ')
+                .next()
+                .unwrap_or(&[]);
+
+            // A lone CR may start one of the empty lines before the request
+            let empty_line = partial_line == b"\r";
+            if !empty_line && !method.iter().all(|byte| is_token_char(*byte as char)) {
+                return ParseError::Malformed;
             }
 
-            match self.parse_header() {
-                Ok((key, value)) => {
-                    self.headers.insert(key, value);
-                }
-                Err(e) => return Err(e),
+            if partial_line.len() > limits.max_request_line_length {
+                return ParseError::URITooLong;
             }
         }
+
+        ParseError::NotEnoughInput
     }
 
-    fn parse_header(&mut self) -> Result<(String, String), ParseError> {
-        let mut maybe_key = None;
-        let mut maybe_value = None;
+    fn parse_request_line(&mut self, input: &[u8], line: Range<usize>) -> Result<(), ParseError> {
+        let line_bytes = &input[line.clone()];
+
+        let method_end = find_byte(line_bytes, b' ').ok_or(ParseError::Malformed)?;
+        let target_end = method_end
+            + 1
+            + find_byte(&line_bytes[method_end + 1..], b' ').ok_or(ParseError::Malformed)?;
+
+        let method = &line_bytes[..method_end];
+        let target = &line_bytes[method_end + 1..target_end];
+        let version = &line_bytes[target_end + 1..];
+
+        if method.is_empty() || !method.iter().all(|byte| is_token_char(*byte as char)) {
+            return Err(ParseError::Malformed);
+        }
+
+        if target.is_empty() || !target.iter().all(|byte| byte.is_ascii_graphic()) {
+            return Err(ParseError::Malformed);
+        }
+
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(if version.starts_with(b"HTTP/") {
+                ParseError::UnsupportedVersion
+            } else {
+                ParseError::Malformed
+            });
+        }
+
+        self.method = line.start..line.start + method_end;
+        self.target = line.start + method_end + 1..line.start + target_end;
+        self.version = line.start + target_end + 1..line.end;
 
-        self.read_until(":")
-            .map(|key| maybe_key = Some(key.to_lowercase()))
-            .and_then(|_| self.expect(":"))
-            .and_then(|_| self.read_until("\r\n"))
-            .map(|value| maybe_value = Some(value))
-            .and_then(|_| self.expect("\r\n"))
-            .map(|_| (maybe_key.unwrap(), maybe_value.unwrap().trim().to_string()))
+        Ok(())
     }
 
-    fn expect(&mut self, expected: &str) -> Result<String, ParseError> {
-        if expected.len() > self.source.len() {
-            return Err(ParseError::NotEnoughInput);
+    fn parse_header(&mut self, line: &[u8]) -> Result<(), ParseError> {
+        let separator = find_byte(line, b':').ok_or(ParseError::Malformed)?;
+        let key = &line[..separator];
+
+        // Also rejects obsolete line folding, as the key would start with whitespace
+        if key.is_empty() || !key.iter().all(|byte| is_token_char(*byte as char)) {
+            return Err(ParseError::Malformed);
         }
 
-        if self.source.starts_with(expected) {
-            let (init, rest) = self.source.split_at(expected.len());
-            let init_as_str = init.to_string();
-            let rest_as_str = rest.to_string();
+        // Values may contain any bytes, anything but UTF-8 is replaced
+        let value = String::from_utf8_lossy(trim_whitespace(&line[separator + 1..]));
 
-            self.source = rest_as_str;
+        self.headers.append(String::from_utf8_lossy(key), value);
 
-            return Ok(init_as_str);
+        Ok(())
+    }
+
+    fn request(&mut self, input: &[u8]) -> Result<Request, ParseError> {
+        // All of these have been checked to be ASCII already
+        let as_str = |range: &Range<usize>| {
+            str::from_utf8(&input[range.clone()]).map_err(|_| ParseError::Malformed)
+        };
+
+        Ok(Request::new(
+            Method::from(as_str(&self.method)?),
+            as_str(&self.target)?.to_string(),
+            as_str(&self.version)?.to_string(),
+            mem::replace(&mut self.headers, HeaderMap::new()),
+        ))
+    }
+}
+
+fn find_byte(bytes: &[u8], needle: u8) -> Option<usize> {
+    bytes.iter().position(|byte| *byte == needle)
+}
+
+fn trim_whitespace(mut bytes: &[u8]) -> &[u8] {
+    while let Some((first, rest)) = bytes.split_first() {
+        if *first != b' ' && *first != b'\t' {
+            break;
         }
+        bytes = rest;
+    }
 
-        Err(ParseError::Fatal)
+    while let Some((last, rest)) = bytes.split_last() {
+        if *last != b' ' && *last != b'\t' {
+            break;
+        }
+        bytes = rest;
     }
 
-    /// Reads in source until delimiter.
-    /// Does not consume delimiter.
-    fn read_until(&mut self, delimiter: &str) -> Result<String, ParseError> {
-        let mut already_read = String::new();
+    bytes
+}
 
-        // TODO: optimize, dont do as many string ops
-        // maybe just keep the index and split at the end
-        // There werent any library functions for that so i skipped it sorry
-        loop {
-            if self.source.len() < delimiter.len() {
-                return Err(ParseError::NotEnoughInput);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
 
-            if self.source.starts_with(delimiter) {
-                return Ok(already_read);
-            }
+    const GET: &[u8] =
+        b"GET /index.html?a=b HTTP/1.1\r\nHost: localhost\r\nAccept: a\r\naccept:  b \r\n\r\n";
+
+    fn parse(input: &[u8]) -> Result<(Request, usize), ParseError> {
+        HTTPParser::new().parse_head(input, &Limits::default())
+    }
 
-            let (first, rest) = self.source.split_at(1);
+    #[test]
+    fn parses_head() {
+        let (request, length) = parse(GET).unwrap();
 
-            already_read.push_str(first);
-            self.source = rest.to_string();
+        assert_eq!(length, GET.len());
+        assert_eq!(*request.method(), Method::Get);
+        assert_eq!(request.target(), "/index.html?a=b");
+        assert_eq!(request.version(), "HTTP/1.1");
+        assert_eq!(request.headers().get("host"), Some("localhost"));
+
+        let accept: Vec<&str> = request.headers().get_all("Accept").collect();
+        assert_eq!(accept, ["a", "b"]);
+    }
+
+    #[test]
+    fn continues_partial_heads() {
+        let mut parser = HTTPParser::new();
+
+        // Also splits every CRLF, which has to be found across calls
+        for end in 1..GET.len() {
+            let result = parser.parse_head(&GET[..end], &Limits::default());
+            assert_eq!(result.err(), Some(ParseError::NotEnoughInput));
         }
+
+        let (request, length) = parser.parse_head(GET, &Limits::default()).unwrap();
+        assert_eq!(length, GET.len());
+        assert_eq!(request.path(), "/index.html");
+        assert_eq!(request.headers().len(), 3);
+    }
+
+    #[test]
+    fn leaves_pipelined_requests() {
+        let mut input = GET.to_vec();
+        input.extend_from_slice(b"DELETE /a HTTP/1.0\r\n\r\nPOST");
+
+        let (_, length) = parse(&input).unwrap();
+        assert_eq!(length, GET.len());
+
+        let (request, second_length) = parse(&input[length..]).unwrap();
+        assert_eq!(*request.method(), Method::Delete);
+        assert_eq!(request.version(), "HTTP/1.0");
+        assert_eq!(&input[length + second_length..], b"POST");
+    }
+
+    #[test]
+    fn skips_empty_lines_before_heads() {
+        let mut input = b"\r\n\r\n".to_vec();
+        input.extend_from_slice(GET);
+        let mut parser = HTTPParser::new();
+
+        for end in 1..input.len() {
+            let result = parser.parse_head(&input[..end], &Limits::default());
+            assert_eq!(result.err(), Some(ParseError::NotEnoughInput));
+        }
+
+        let (request, length) = parser.parse_head(&input, &Limits::default()).unwrap();
+        assert_eq!(length, input.len());
+        assert_eq!(*request.method(), Method::Get);
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        let heads: &[&[u8]] = &[
+            b"GET /\r\n\r\n",
+            b"GET  / HTTP/1.1\r\n\r\n",
+            b"G(ET / HTTP/1.1\r\n\r\n",
+            b"GET /a b HTTP/1.1\r\n\r\n",
+            b"GET / FTP/1.0\r\n\r\n",
+            b"GET / HTTP/1.1\r\nNo colon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: b\r\n  folded\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA b: c\r\n\r\n",
+        ];
+
+        for head in heads {
+            assert_eq!(parse(head).err(), Some(ParseError::Malformed));
+        }
+
+        // Rejected before the line is complete, e.g. TLS handshakes
+        assert_eq!(parse(b"\x16\x03\x01").err(), Some(ParseError::Malformed));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let result = parse(b"GET / HTTP/2.0\r\n\r\n");
+        assert_eq!(result.err(), Some(ParseError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn accepts_extension_methods() {
+        let (request, _) = parse(b"PROPFIND / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(*request.method(), Method::Extension("PROPFIND".to_string()));
+    }
+
+    #[test]
+    fn limits_request_line() {
+        let limits = Limits {
+            max_request_line_length: 20,
+            ..Limits::default()
+        };
+        let parse = |input: &[u8]| HTTPParser::new().parse_head(input, &limits).err();
+
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(
+            parse(b"GET /0123456789 HTTP/1.1\r\n\r\n"),
+            Some(ParseError::URITooLong)
+        );
+        // Long lines are rejected before they are complete
+        assert_eq!(
+            parse(b"GET /0123456789abcdefgh"),
+            Some(ParseError::URITooLong)
+        );
+    }
+
+    #[test]
+    fn limits_header_count() {
+        let limits = Limits {
+            max_header_count: 2,
+            ..Limits::default()
+        };
+        let parse = |input: &[u8]| HTTPParser::new().parse_head(input, &limits).err();
+
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n"), None);
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"),
+            Some(ParseError::HeadTooLarge)
+        );
+    }
+
+    #[test]
+    fn limits_head_size() {
+        let limits = Limits {
+            max_head_size: 32,
+            ..Limits::default()
+        };
+        let parse = |input: &[u8]| HTTPParser::new().parse_head(input, &limits).err();
+
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nA: 012345678\r\n\r\n"), None);
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nA: 0123456789abcdefghij\r\n\r\n"),
+            Some(ParseError::HeadTooLarge)
+        );
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nA: 0123456789abcdefghijklmnop"),
+            Some(ParseError::HeadTooLarge)
+        );
     }
 }
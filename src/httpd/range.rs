@@ -0,0 +1,514 @@
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{cmp, mem, ops::Range};
+
+use super::body::{Body, BodyStream};
+use super::method::Method;
+use super::request::Request;
+use super::response::Response;
+use super::status::Status;
+
+// Separates the parts of multipart/byteranges bodies
+const BOUNDARY: &str = "3d6b6a416f9b5fd2";
+
+// More ranges than this are most likely an attempt to waste our resources
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    /// Missing, invalid or outdated, the whole body is sent
+    Ignore,
+    Unsatisfiable,
+    /// Sorted and without overlaps, so streams can be read in one go
+    Ranges(Vec<Range<usize>>),
+}
+
+/// Replaces the response with the parts requested in the `Range` header.
+///
+/// Only applies to successful `GET` requests for bodies of known length, all
+/// of them announce support with `Accept-Ranges`.
+pub(super) fn apply_range(request: &Request, response: &mut Response) {
+    if response.status != Status::OK {
+        return;
+    }
+
+    let length = match (request.method(), response.body.length()) {
+        (Method::Get, Some(length)) | (Method::Head, Some(length)) => length,
+        _ => return,
+    };
+
+    if !response.headers.contains_key("Accept-Ranges") {
+        response.headers.insert("Accept-Ranges", "bytes");
+    }
+
+    // Range is only defined for GET (RFC 7233, 3.1)
+    if *request.method() != Method::Get || !if_range_matches(request, response) {
+        return;
+    }
+
+    let ranges = match request.headers().get("Range") {
+        Some(range) => match parse_range(range, length) {
+            RangeRequest::Ignore => return,
+            RangeRequest::Unsatisfiable => {
+                response.status = Status::RangeNotSatisfiable;
+                response.body = Body::Bytes(vec![]);
+                response.headers.remove("Content-Type");
+                response.headers.remove("Content-Length");
+                response
+                    .headers
+                    .insert("Content-Range", format!("bytes */{}", length));
+                return;
+            }
+            RangeRequest::Ranges(ranges) => ranges,
+        },
+        None => return,
+    };
+
+    let body = mem::replace(&mut response.body, Body::Bytes(vec![]));
+    let (parts, trailer) = if ranges.len() == 1 {
+        let range = ranges[0].clone();
+        response
+            .headers
+            .insert("Content-Range", content_range(&range, length));
+
+        (vec![(Vec::new(), range)], Vec::new())
+    } else {
+        let content_type = response
+            .headers
+            .get("Content-Type")
+            .map(ToString::to_string);
+        response.headers.insert(
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", BOUNDARY),
+        );
+
+        let parts = ranges
+            .into_iter()
+            .map(|range| {
+                let mut head = format!("\r\n--{}\r\n", BOUNDARY);
+                if let Some(content_type) = &content_type {
+                    head.push_str(&format!("Content-Type: {}\r\n", content_type));
+                }
+                head.push_str(&format!(
+                    "Content-Range: {}\r\n\r\n",
+                    content_range(&range, length)
+                ));
+
+                (head.into_bytes(), range)
+            })
+            .collect();
+
+        (parts, format!("\r\n--{}--\r\n", BOUNDARY).into_bytes())
+    };
+
+    response.status = Status::PartialContent;
+    response.body = match body {
+        Body::Bytes(bytes) => {
+            let mut partial = Vec::new();
+            for (head, range) in parts {
+                partial.extend(head);
+                partial.extend(&bytes[range]);
+            }
+            partial.extend(trailer);
+
+            Body::Bytes(partial)
+        }
+        Body::Stream(stream) => Body::Stream(Box::new(RangeStream::new(stream, parts, trailer))),
+    };
+
+    if let Some(length) = response.body.length() {
+        response.headers.set_content_length(length);
+    }
+}
+
+/// Whether the representation the client has parts of is still current
+/// (RFC 7233, 3.2)
+fn if_range_matches(request: &Request, response: &Response) -> bool {
+    let if_range = match request.headers().get("If-Range") {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+
+    // Entity tags have to match strongly, dates exactly
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        match response.headers.get("ETag") {
+            Some(etag) => !etag.starts_with("W/") && etag.trim() == if_range,
+            None => false,
+        }
+    } else {
+        response.headers.get("Last-Modified").map(str::trim) == Some(if_range)
+    }
+}
+
+/// Parses a `Range` header like `bytes=0-499, 1000-, -200` (RFC 7233, 2.1)
+fn parse_range(header: &str, length: usize) -> RangeRequest {
+    let header = header.trim();
+    // Slicing could split a character, `get` doesn't panic then
+    let unit = header.get(..6);
+    if !unit.map_or(false, |unit| unit.eq_ignore_ascii_case("bytes=")) {
+        return RangeRequest::Ignore;
+    }
+
+    let mut ranges = Vec::new();
+
+    for spec in header[6..]
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        if ranges.len() == MAX_RANGES {
+            return RangeRequest::Ignore;
+        }
+
+        let separator = match spec.find('-') {
+            Some(separator) => separator,
+            None => return RangeRequest::Ignore,
+        };
+        let (first, last) = (spec[..separator].trim(), spec[separator + 1..].trim());
+
+        let range = if first.is_empty() {
+            // The last `suffix` bytes
+            let suffix = match last.parse::<usize>() {
+                Ok(suffix) => suffix,
+                Err(_) => return RangeRequest::Ignore,
+            };
+
+            length - cmp::min(suffix, length)..length
+        } else {
+            let first = match first.parse::<usize>() {
+                Ok(first) => first,
+                Err(_) => return RangeRequest::Ignore,
+            };
+            let last = match last {
+                "" => length.saturating_sub(1),
+                _ => match last.parse::<usize>() {
+                    Ok(last) if last >= first => cmp::min(last, length.saturating_sub(1)),
+                    _ => return RangeRequest::Ignore,
+                },
+            };
+
+            first..last + 1
+        };
+
+        // Unsatisfiable ranges are skipped, as long as some other one is valid
+        if range.start < length && range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    // Overlapping ranges are combined (RFC 7233, 4.1)
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if let Some(previous) = merged.last_mut() {
+            if range.start <= previous.end {
+                previous.end = cmp::max(previous.end, range.end);
+                continue;
+            }
+        }
+        merged.push(range);
+    }
+
+    RangeRequest::Ranges(merged)
+}
+
+fn content_range(range: &Range<usize>, length: usize) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, length)
+}
+
+/// Reads the requested parts of a stream, each preceded by its head
+struct RangeStream {
+    inner: Box<dyn BodyStream>,
+    // Position in the inner stream
+    offset: usize,
+    parts: Vec<(Vec<u8>, Range<usize>)>,
+    part: usize,
+    trailer: Vec<u8>,
+    // Head or trailer bytes that still have to be written
+    pending: Vec<u8>,
+    head_sent: bool,
+    length: usize,
+}
+
+impl RangeStream {
+    fn new(
+        inner: Box<dyn BodyStream>,
+        parts: Vec<(Vec<u8>, Range<usize>)>,
+        trailer: Vec<u8>,
+    ) -> RangeStream {
+        let parts_length: usize = parts
+            .iter()
+            .map(|(head, range)| head.len() + range.len())
+            .sum();
+        let length = parts_length + trailer.len();
+
+        RangeStream {
+            inner,
+            offset: 0,
+            parts,
+            part: 0,
+            trailer,
+            pending: vec![],
+            head_sent: false,
+            length,
+        }
+    }
+}
+
+impl BodyStream for RangeStream {
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut written = 0;
+
+        while written < buffer.len() {
+            if !self.pending.is_empty() {
+                let length = cmp::min(buffer.len() - written, self.pending.len());
+                buffer[written..written + length].copy_from_slice(&self.pending[..length]);
+                self.pending.drain(..length);
+                written += length;
+                continue;
+            }
+
+            let range = match self.parts.get(self.part) {
+                Some((head, range)) if !self.head_sent => {
+                    self.pending.extend(head);
+                    self.head_sent = true;
+                    range.clone()
+                }
+                Some((_, range)) => range.clone(),
+                None => {
+                    if self.trailer.is_empty() {
+                        break;
+                    }
+                    self.pending = mem::replace(&mut self.trailer, vec![]);
+                    continue;
+                }
+            };
+
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            // Bytes before the range are read into the free space and dropped
+            let free = &mut buffer[written..];
+            let (wanted, skip) = if self.offset < range.start {
+                (cmp::min(free.len(), range.start - self.offset), true)
+            } else {
+                (cmp::min(free.len(), range.end - self.offset), false)
+            };
+
+            let read = self.inner.read(&mut free[..wanted]);
+            if read == 0 {
+                // The stream ended early, so the body can't be completed
+                break;
+            }
+
+            self.offset += read;
+            if !skip {
+                written += read;
+            }
+
+            if self.offset == range.end {
+                self.part += 1;
+                self.head_sent = false;
+            }
+        }
+
+        written
+    }
+
+    fn length(&self) -> Option<usize> {
+        Some(self.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::response::ResponseBuilder;
+    use super::super::tests::request;
+    use super::*;
+
+    const CONTENT: &[u8] = b"Hello, world";
+
+    /// Streams its content in pieces of two bytes
+    struct SliceStream(&'static [u8]);
+
+    impl BodyStream for SliceStream {
+        fn read(&mut self, buffer: &mut [u8]) -> usize {
+            let length = cmp::min(cmp::min(buffer.len(), 2), self.0.len());
+            buffer[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            length
+        }
+
+        fn length(&self) -> Option<usize> {
+            Some(self.0.len())
+        }
+    }
+
+    fn ranges(ranges: &[Range<usize>]) -> RangeRequest {
+        RangeRequest::Ranges(ranges.to_vec())
+    }
+
+    fn apply(headers: &[(&str, &str)], body: Body) -> Response {
+        let mut response = ResponseBuilder::new(Status::OK)
+            .header("Content-Type", "text/plain")
+            .header("ETag", "\"1\"")
+            .finalize();
+        response.body = body;
+
+        apply_range(&request(Method::Get, "/", headers), &mut response);
+        response
+    }
+
+    /// Reads the whole body, streams with a buffer that cuts heads and parts
+    fn read_body(body: Body) -> Vec<u8> {
+        match body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream(mut stream) => {
+                let mut data = vec![];
+                let mut buffer = [0; 3];
+                loop {
+                    let read = stream.read(&mut buffer);
+                    if read == 0 {
+                        return data;
+                    }
+                    data.extend_from_slice(&buffer[..read]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parses_rfc_7233_examples() {
+        assert_eq!(parse_range("bytes=0-499", 10000), ranges(&[0..500]));
+        assert_eq!(parse_range("bytes=500-999", 10000), ranges(&[500..1000]));
+        assert_eq!(parse_range("bytes=-500", 10000), ranges(&[9500..10000]));
+        assert_eq!(parse_range("bytes=9500-", 10000), ranges(&[9500..10000]));
+        assert_eq!(
+            parse_range("bytes=0-0,-1", 10000),
+            ranges(&[0..1, 9999..10000])
+        );
+        // Overlapping and adjacent ranges are merged
+        assert_eq!(
+            parse_range("bytes=500-600,601-999", 10000),
+            ranges(&[500..1000])
+        );
+        assert_eq!(
+            parse_range("bytes=500-700,601-999", 10000),
+            ranges(&[500..1000])
+        );
+    }
+
+    #[test]
+    fn sorts_and_clamps_ranges() {
+        assert_eq!(parse_range("Bytes= 8-9 , 0-1", 10), ranges(&[0..2, 8..10]));
+        assert_eq!(parse_range("bytes=5-100", 10), ranges(&[5..10]));
+        assert_eq!(parse_range("bytes=-100", 10), ranges(&[0..10]));
+        assert_eq!(parse_range("bytes=0-1,-5", 6), ranges(&[0..6]));
+        assert_eq!(parse_range("bytes=20-, 3-3", 10), ranges(&[3..4]));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_invalid_ranges() {
+        let too_many = format!("bytes={}", "0-0,".repeat(MAX_RANGES + 1));
+
+        for header in &[
+            "items=0-1",
+            "bytes€0-1",
+            "bytes=1-0",
+            "bytes=a-b",
+            "bytes=5",
+            "bytes=-",
+            &too_many,
+        ] {
+            assert_eq!(parse_range(header, 10), RangeRequest::Ignore);
+        }
+    }
+
+    #[test]
+    fn answers_single_ranges() {
+        for body in vec![
+            Body::Bytes(CONTENT.to_vec()),
+            Body::Stream(Box::new(SliceStream(CONTENT))),
+        ] {
+            let response = apply(&[("Range", "bytes=-5")], body);
+
+            assert_eq!(response.status, Status::PartialContent);
+            assert_eq!(response.headers.get("Content-Range"), Some("bytes 7-11/12"));
+            assert_eq!(response.headers.get("Content-Length"), Some("5"));
+            assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+            assert_eq!(read_body(response.body), b"world");
+        }
+    }
+
+    #[test]
+    fn answers_multiple_ranges() {
+        let expected = format!(
+            "\r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/12\r\n\r\nHe\
+             \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 7-11/12\r\n\r\nworld\
+             \r\n--{0}--\r\n",
+            BOUNDARY
+        );
+
+        for body in vec![
+            Body::Bytes(CONTENT.to_vec()),
+            Body::Stream(Box::new(SliceStream(CONTENT))),
+        ] {
+            let response = apply(&[("Range", "bytes=7-,0-1")], body);
+            let length = expected.len().to_string();
+
+            assert_eq!(response.status, Status::PartialContent);
+            assert_eq!(
+                response.headers.content_type(),
+                Some(format!("multipart/byteranges; boundary={}", BOUNDARY).as_str())
+            );
+            assert_eq!(
+                response.headers.get("Content-Length"),
+                Some(length.as_str())
+            );
+            assert_eq!(read_body(response.body), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn answers_unsatisfiable_ranges() {
+        let response = apply(&[("Range", "bytes=12-")], Body::Bytes(CONTENT.to_vec()));
+
+        assert_eq!(response.status, Status::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */12"));
+        assert_eq!(response.body.length(), Some(0));
+    }
+
+    #[test]
+    fn checks_if_range() {
+        let response = apply(
+            &[("Range", "bytes=0-1"), ("If-Range", "\"1\"")],
+            Body::Bytes(CONTENT.to_vec()),
+        );
+        assert_eq!(response.status, Status::PartialContent);
+
+        // The client's copy is outdated, so it gets the whole body
+        let response = apply(
+            &[("Range", "bytes=0-1"), ("If-Range", "\"2\"")],
+            Body::Bytes(CONTENT.to_vec()),
+        );
+        assert_eq!(response.status, Status::OK);
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+        assert_eq!(read_body(response.body), CONTENT);
+    }
+}
@@ -1,42 +1,139 @@
-use alloc::{collections::BTreeMap, string::String};
+use super::headers::HeaderMap;
+use super::method::Method;
+use super::url::{parse_query, percent_decode};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 
 #[derive(Clone, Debug)]
 pub struct Request {
-    method: String,
+    method: Method,
+    target: String,
     path: String,
+    query: BTreeMap<String, Vec<String>>,
     version: String,
-    headers: BTreeMap<String, String>,
+    headers: HeaderMap,
+    trailers: HeaderMap,
 }
 
 impl Request {
-    pub fn new(
-        method: String,
-        path: String,
-        version: String,
-        headers: BTreeMap<String, String>,
-    ) -> Request {
+    /// Creates a request, `target` is split into the decoded path and query.
+    pub fn new(method: Method, target: String, version: String, headers: HeaderMap) -> Request {
+        let (path, query) = match target.find('?') {
+            Some(separator) => (&target[..separator], parse_query(&target[separator + 1..])),
+            None => (&target[..], BTreeMap::new()),
+        };
+        let path = percent_decode(path, false);
+
         Request {
             method,
+            target,
             path,
+            query,
             version,
             headers,
+            trailers: HeaderMap::new(),
         }
     }
 
-    pub fn method(&self) -> &str {
+    pub fn method(&self) -> &Method {
         &self.method
     }
 
+    /// The request-target as sent by the client, without any decoding
+    #[allow(dead_code)]
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The percent-decoded path, without the query string
     pub fn path(&self) -> &str {
         &self.path
     }
 
+    /// The segments of the path, decoded one by one so that an encoded `/`
+    /// stays part of its segment
+    pub fn path_segments(&self) -> impl Iterator<Item = String> + '_ {
+        self.target
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .map(|segment| percent_decode(segment, false))
+    }
+
+    /// All query parameters with all of their values, in order
     #[allow(dead_code)]
+    pub fn query(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.query
+    }
+
+    /// The first value of the query parameter `key`
+    #[allow(dead_code)]
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        self.query
+            .get(key)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
     pub fn version(&self) -> &str {
         &self.version
     }
 
-    pub fn headers(&self) -> &BTreeMap<String, String> {
+    pub fn headers(&self) -> &HeaderMap {
         &self.headers
     }
+
+    /// Headers can be changed by middlewares before the request is routed.
+    #[allow(dead_code)]
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Trailer fields sent after a chunked body
+    #[allow(dead_code)]
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub(super) fn set_trailers(&mut self, trailers: HeaderMap) {
+        self.trailers = trailers;
+    }
+
+    /// Whether the client wants the connection to stay open after the response.
+    ///
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent,
+    /// HTTP/1.0 connections only if `Connection: keep-alive` is sent.
+    pub fn keep_alive(&self) -> bool {
+        if self.version == "HTTP/1.1" {
+            !self.headers.connection("close")
+        } else {
+            self.headers.connection("keep-alive")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::request;
+    use super::*;
+
+    #[test]
+    fn splits_target() {
+        let request = request(Method::Get, "/a%20b/c?d=e&d=f", &[]);
+
+        assert_eq!(request.target(), "/a%20b/c?d=e&d=f");
+        assert_eq!(request.path(), "/a b/c");
+        assert_eq!(request.query()["d"], ["e", "f"]);
+        assert_eq!(request.query_param("d"), Some("e"));
+        assert_eq!(request.query_param("g"), None);
+    }
+
+    #[test]
+    fn decodes_segments_separately() {
+        let request = request(Method::Get, "/files/a%2Fb/c%20d?e=%2F", &[]);
+        let segments: Vec<String> = request.path_segments().collect();
+
+        assert_eq!(request.path(), "/files/a/b/c d");
+        assert_eq!(segments, ["", "files", "a/b", "c d"]);
+    }
 }
@@ -1,71 +1,115 @@
+use super::body::{Body, BodyStream};
+use super::cache::{self, CacheControl};
+use super::headers::HeaderMap;
 use super::status::Status;
-use alloc::{
-    collections::BTreeMap,
-    string::{String, ToString},
-    vec::Vec,
-};
+use alloc::{boxed::Box, string::ToString, vec::Vec};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Response {
     pub status: Status,
-    pub headers: BTreeMap<String, String>,
-    pub body: Vec<u8>,
+    pub headers: HeaderMap,
+    pub body: Body,
 }
 
 impl Response {
-    pub fn new(status: Status, headers: BTreeMap<String, String>, body: Vec<u8>) -> Response {
+    pub fn new<B: Into<Body>>(status: Status, headers: HeaderMap, body: B) -> Response {
         Response {
             status,
             headers,
-            body,
+            body: body.into(),
         }
     }
 }
 
 pub struct ResponseBuilder {
     status: Status,
-    headers: BTreeMap<String, String>,
-    body: Vec<u8>,
+    headers: HeaderMap,
+    body: Body,
+    etag: bool,
 }
 
 impl ResponseBuilder {
     pub fn new(status: Status) -> ResponseBuilder {
         ResponseBuilder {
             status,
-            headers: BTreeMap::new(),
-            body: vec![],
+            headers: HeaderMap::new(),
+            body: Body::Bytes(vec![]),
+            etag: false,
         }
     }
 
+    /// Sets the header `key`, replacing previous values.
     pub fn header<KType: ToString, VType: ToString>(mut self, key: KType, value: VType) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        self.headers.insert(key, value);
+        self
+    }
+
+    /// Adds another value for the header `key`, e.g. for multiple `Set-Cookie`s.
+    #[allow(dead_code)]
+    pub fn append_header<KType: ToString, VType: ToString>(
+        mut self,
+        key: KType,
+        value: VType,
+    ) -> Self {
+        self.headers.append(key, value);
         self
     }
 
     pub fn body(mut self, body: Vec<u8>) -> Self {
-        self.body = body;
+        self.body = Body::Bytes(body);
         self
     }
 
     pub fn body_html(mut self, body_str: &str) -> Self {
-        self.headers
-            .insert("Content-Type".to_string(), "text/html".to_string());
-        self.body = body_str.into();
+        self.headers.set_content_type("text/html");
+        self.body = Body::Bytes(body_str.into());
+
+        self
+    }
 
+    /// Uses a stream as body, which is read while the response is sent.
+    pub fn body_stream<S: BodyStream + 'static>(mut self, stream: S) -> Self {
+        self.body = Body::Stream(Box::new(stream));
         self
     }
 
+    /// Sets a strong `ETag` computed from the body when the response is built.
+    ///
+    /// Streamed bodies can't be hashed in advance, set the header yourself.
+    #[allow(dead_code)]
+    pub fn etag(mut self) -> Self {
+        self.etag = true;
+        self
+    }
+
+    pub fn cache_control(mut self, policy: CacheControl) -> Self {
+        self.headers.insert("Cache-Control", policy);
+        self
+    }
+
+    /// Builds the response, the body is dropped if the status doesn't allow one.
     pub fn finalize(mut self) -> Response {
-        if self.body.len() > 0 {
-            self.headers
-                .insert("Content-Length".into(), self.body.len().to_string());
-
-            if !self.headers.contains_key("Content-Type") {
-                self.headers.insert(
-                    "Content-Type".into(),
-                    "application/octet-stream".to_string(),
-                );
+        if !self.status.allows_body() {
+            self.body = Body::Bytes(vec![]);
+        }
+
+        let has_body = match &self.body {
+            Body::Bytes(bytes) => bytes.len() > 0,
+            Body::Stream(_) => true,
+        };
+
+        if has_body {
+            if let Some(length) = self.body.length() {
+                self.headers.set_content_length(length);
             }
+
+            if self.headers.content_type().is_none() {
+                self.headers.set_content_type("application/octet-stream");
+            }
+        }
+
+        if let (true, Body::Bytes(bytes)) = (self.etag, &self.body) {
+            self.headers.insert("ETag", cache::etag(bytes));
         }
 
         Response::new(self.status, self.headers, self.body)
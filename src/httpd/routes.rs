@@ -1,56 +1,112 @@
+use super::headers::join_list;
+use super::method::Method;
 use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::status::Status;
 use alloc::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     string::{String, ToString},
 };
 
 pub enum Routes<'a, R> {
-    NotMatched(&'a Request),
+    // Also keeps the methods of all routes whose path matched
+    NotMatched(&'a Request, BTreeSet<Method>),
     Matched(R),
 }
 
 impl<'a, R> Routes<'a, R> {
     pub fn init<'b>(request: &'b Request) -> Routes<'b, R> {
-        Routes::NotMatched(request)
+        Routes::NotMatched(request, BTreeSet::new())
     }
 
+    /// Adds a route for `method` and `pattern`.
+    ///
+    /// `GET` routes also match `HEAD` requests, the server then drops the
+    /// body of the response. To handle `HEAD` differently, add a `HEAD` route
+    /// before the `GET` route.
     pub fn route<F: FnOnce(&'a Request, BTreeMap<String, String>) -> R>(
         self,
-        method: &str,
+        method: Method,
         pattern: &str,
         make_response: F,
     ) -> Routes<'a, R> {
         match self {
             Routes::Matched(result) => Routes::Matched(result),
-            Routes::NotMatched(request) => {
-                if method == request.method() {
-                    let matched_path = match_path(pattern, &request.path());
+            Routes::NotMatched(request, mut allowed_methods) => {
+                let matched_path = match_path(pattern, request.path_segments());
 
-                    match matched_path {
-                        None => Routes::NotMatched(request),
-                        Some(args) => Routes::Matched(make_response(request, args)),
+                match matched_path {
+                    None => Routes::NotMatched(request, allowed_methods),
+                    Some(args) => {
+                        let method_matches = &method == request.method()
+                            || (method == Method::Get && *request.method() == Method::Head);
+
+                        if method_matches {
+                            Routes::Matched(make_response(request, args))
+                        } else {
+                            if method == Method::Get {
+                                allowed_methods.insert(Method::Head);
+                            }
+                            allowed_methods.insert(method);
+
+                            Routes::NotMatched(request, allowed_methods)
+                        }
                     }
-                } else {
-                    Routes::NotMatched(request)
                 }
             }
         }
     }
 
+    /// Handles requests that no route matched.
     pub fn catch_all<F: FnOnce(&'a Request, BTreeMap<String, String>) -> R>(
         self,
         make_response: F,
     ) -> R {
         match self {
             Routes::Matched(result) => result,
-            Routes::NotMatched(request) => make_response(request, BTreeMap::new()),
+            Routes::NotMatched(request, _) => make_response(request, BTreeMap::new()),
         }
     }
 }
 
-fn match_path(pattern: &str, path: &str) -> Option<BTreeMap<String, String>> {
+impl<'a> Routes<'a, Response> {
+    /// Answers requests whose path matched a route with a different method.
+    ///
+    /// These get `405 Method Not Allowed` (or `200 OK` for `OPTIONS`) and an
+    /// `Allow` header, add this before `catch_all`.
+    pub fn method_not_allowed(self) -> Routes<'a, Response> {
+        match self {
+            Routes::NotMatched(request, mut allowed_methods) => {
+                if allowed_methods.is_empty() {
+                    return Routes::NotMatched(request, allowed_methods);
+                }
+
+                allowed_methods.insert(Method::Options);
+
+                let allow = join_list(allowed_methods.iter().map(Method::as_str));
+
+                let status = if *request.method() == Method::Options {
+                    Status::OK
+                } else {
+                    Status::MethodNotAllowed
+                };
+
+                Routes::Matched(
+                    ResponseBuilder::new(status)
+                        .header("Allow", allow)
+                        .finalize(),
+                )
+            }
+            matched => matched,
+        }
+    }
+}
+
+fn match_path<I: Iterator<Item = String>>(
+    pattern: &str,
+    mut path_parts: I,
+) -> Option<BTreeMap<String, String>> {
     let mut pattern_parts = pattern.split("/");
-    let mut path_parts = path.split("/");
 
     let mut args = BTreeMap::new();
 
@@ -61,10 +117,7 @@ fn match_path(pattern: &str, path: &str) -> Option<BTreeMap<String, String>> {
             // If both still have a part, compare them and look for variables (starting with ":")
             (Some(pattern_part), Some(path_part)) => {
                 if pattern_part.len() >= 2 && pattern_part.split_at(1).0 == ":" {
-                    args.insert(
-                        (pattern_part.split_at(1).1).to_string(),
-                        path_part.to_string(),
-                    );
+                    args.insert((pattern_part.split_at(1).1).to_string(), path_part);
                 } else if pattern_part != path_part {
                     return None;
                 }
@@ -74,3 +127,99 @@ fn match_path(pattern: &str, path: &str) -> Option<BTreeMap<String, String>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::body::Body;
+    use super::super::tests::request;
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn pins(method: Method, path: &str) -> Response {
+        let request = request(method, path, &[]);
+
+        Routes::init(&request)
+            .route(Method::Get, "/pins", |_req, _args| {
+                ResponseBuilder::new(Status::OK)
+                    .body(b"pins".to_vec())
+                    .finalize()
+            })
+            .route(Method::Post, "/pins/:name", |_req, args| {
+                ResponseBuilder::new(Status::OK)
+                    .body(args["name"].as_bytes().to_vec())
+                    .finalize()
+            })
+            .method_not_allowed()
+            .catch_all(|_req, _args| ResponseBuilder::new(Status::NotFound).finalize())
+    }
+
+    fn code(response: &Response) -> u16 {
+        response.status.numerical_and_text().0
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        match response.body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream(_) => panic!("Routes only answer with bytes"),
+        }
+    }
+
+    #[test]
+    fn matches_methods_and_arguments() {
+        let response = pins(Method::Post, "/pins/led");
+
+        assert_eq!(code(&response), 200);
+        assert_eq!(body(response), b"led");
+    }
+
+    #[test]
+    fn answers_head_with_get_routes() {
+        let response = pins(Method::Head, "/pins");
+
+        assert_eq!(code(&response), 200);
+        assert_eq!(body(response), b"pins");
+    }
+
+    #[test]
+    fn prefers_head_routes_before_get_routes() {
+        let request = request(Method::Head, "/pins", &[]);
+        let response = Routes::init(&request)
+            .route(Method::Head, "/pins", |_req, _args| {
+                ResponseBuilder::new(Status::OK)
+                    .body(b"head".to_vec())
+                    .finalize()
+            })
+            .route(Method::Get, "/pins", |_req, _args| {
+                ResponseBuilder::new(Status::OK)
+                    .body(b"get".to_vec())
+                    .finalize()
+            })
+            .method_not_allowed()
+            .catch_all(|_req, _args| ResponseBuilder::new(Status::NotFound).finalize());
+
+        assert_eq!(body(response), b"head");
+    }
+
+    #[test]
+    fn answers_other_methods_with_allow() {
+        let response = pins(Method::Delete, "/pins");
+
+        assert_eq!(code(&response), 405);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(response.body.length(), Some(0));
+    }
+
+    #[test]
+    fn answers_options_with_allow() {
+        let response = pins(Method::Options, "/pins/led");
+
+        assert_eq!(code(&response), 200);
+        assert_eq!(response.headers.get("Allow"), Some("POST, OPTIONS"));
+    }
+
+    #[test]
+    fn passes_unknown_paths_to_catch_all() {
+        assert_eq!(code(&pins(Method::Get, "/pixels")), 404);
+        assert_eq!(code(&pins(Method::Options, "/pins/led/on")), 404);
+    }
+}
@@ -0,0 +1,96 @@
+use alloc::vec::Vec;
+use log::trace;
+use smoltcp::socket::TcpSocket;
+
+use super::error::Error;
+
+// Clients that don't read what is sent further than this are disconnected
+const MAX_QUEUED_OUTPUT: usize = 4096;
+
+/// Bytes waiting for space in the socket's send buffer.
+///
+/// Partial sends only move an offset, sent bytes are dropped once that is
+/// cheaper than moving the rest of the buffer.
+#[derive(Debug, Default)]
+pub struct SendBuffer {
+    data: Vec<u8>,
+    // Bytes at the start of `data` that were sent already
+    offset: usize,
+    // Set when the client stopped reading
+    stalled: bool,
+}
+
+impl SendBuffer {
+    pub fn new() -> SendBuffer {
+        SendBuffer::default()
+    }
+
+    /// Number of bytes that weren't sent yet
+    pub fn len(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether more may be queued for a connection that stays open.
+    ///
+    /// Once more than `MAX_QUEUED_OUTPUT` bytes pile up, the client counts as
+    /// stalled and every further `send` fails.
+    pub fn has_room(&mut self) -> bool {
+        if self.len() > MAX_QUEUED_OUTPUT {
+            self.stalled = true;
+        }
+
+        !self.stalled
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.extend(&[byte]);
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        if self.offset > 0 && self.offset >= self.len() {
+            self.data.drain(..self.offset);
+            self.offset = 0;
+        }
+
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Sends as much as the socket takes, returns the number of bytes sent.
+    pub fn send(&mut self, socket: &mut TcpSocket) -> Result<usize, Error> {
+        if self.stalled {
+            return Err(Error::Stalled);
+        }
+
+        if self.is_empty() || !socket.can_send() {
+            return Ok(0);
+        }
+
+        let bytes_sent = socket
+            .send_slice(&self.data[self.offset..])
+            .map_err(Error::Send)?;
+        trace!("{} bytes sent", bytes_sent);
+
+        self.offset += bytes_sent;
+        Ok(bytes_sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stalls_once_too_much_is_queued() {
+        let mut buffer = SendBuffer::new();
+
+        buffer.extend(&[0; MAX_QUEUED_OUTPUT]);
+        assert!(buffer.has_room());
+
+        buffer.push(0);
+        assert!(!buffer.has_room());
+    }
+}
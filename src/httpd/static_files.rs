@@ -0,0 +1,380 @@
+use alloc::{format, string::String};
+use core::cmp;
+
+use super::body::BodyStream;
+use super::cache::CacheControl;
+use super::method::Method;
+use super::middleware::Middleware;
+use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::status::Status;
+
+/// A file that is embedded into the binary.
+///
+/// Tables of these are generated from a directory by the build script.
+pub struct StaticFile {
+    /// Path relative to the embedded directory, separated by `/`
+    pub path: &'static str,
+    pub content: &'static [u8],
+    /// Strong entity tag of the content, including the quotes
+    pub etag: &'static str,
+    /// Gzipped content and its entity tag, if compressing made it smaller
+    pub gzip: Option<(&'static [u8], &'static str)>,
+}
+
+/// Serves embedded files for `GET` and `HEAD` requests.
+///
+/// As a middleware, files take precedence over routes. Requests for paths
+/// that aren't embedded are passed on to the routes.
+///
+/// By default, browsers have to revalidate files before using their cached
+/// copy, which is answered with `304 Not Modified` if the file didn't change.
+/// Only the entity tag is used for that, so that the build stays
+/// reproducible.
+///
+/// Files are sent gzipped to clients that accept it, if there is a gzipped
+/// version.
+pub struct StaticFiles {
+    files: &'static [StaticFile],
+    cache_control: CacheControl,
+}
+
+impl StaticFiles {
+    pub fn new(files: &'static [StaticFile]) -> StaticFiles {
+        StaticFiles {
+            files,
+            cache_control: CacheControl::NoCache,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn cache_control(mut self, policy: CacheControl) -> Self {
+        self.cache_control = policy;
+        self
+    }
+
+    /// Looks up the file for a request path, directories are served by their
+    /// `index.html`.
+    ///
+    /// Paths that try to leave the directory using `..` never match.
+    #[allow(dead_code)]
+    pub fn find(&self, path: &str) -> Option<&'static StaticFile> {
+        normalize_path(path.split('/')).and_then(|path| self.find_normalized(&path))
+    }
+
+    fn find_normalized(&self, path: &str) -> Option<&'static StaticFile> {
+        let index = if path.is_empty() {
+            String::from("index.html")
+        } else {
+            format!("{}/index.html", path)
+        };
+
+        self.files
+            .iter()
+            .find(|file| file.path == path)
+            .or_else(|| self.files.iter().find(|file| file.path == index))
+    }
+
+    /// The response for a request, if it is for an embedded file
+    pub fn response(&self, request: &Request) -> Option<Response> {
+        match request.method() {
+            Method::Get | Method::Head => {}
+            _ => return None,
+        }
+
+        let path = match normalize_path(request.path_segments()) {
+            Some(path) => path,
+            None => return Some(ResponseBuilder::new(Status::BadRequest).finalize()),
+        };
+        let file = self.find_normalized(&path)?;
+
+        // Relative links in an index only resolve within its directory if
+        // the path ends with a slash
+        if file.path != path && !request.path().ends_with('/') {
+            let target = request.target();
+            let location = match target.find('?') {
+                Some(query) => format!("{}/{}", &target[..query], &target[query..]),
+                None => format!("{}/", target),
+            };
+
+            return Some(
+                ResponseBuilder::new(Status::MovedPermanently)
+                    .header("Location", location)
+                    .finalize(),
+            );
+        }
+
+        let response = ResponseBuilder::new(Status::OK)
+            .header("Content-Type", mime_type(file.path))
+            .cache_control(self.cache_control);
+
+        // With a gzipped version, caches have to keep both apart (RFC 7231, 7.1.4)
+        let response = match file.gzip {
+            Some((content, etag)) if accepts_gzip(request) => response
+                .header("Content-Encoding", "gzip")
+                .header("Vary", "Accept-Encoding")
+                .header("ETag", etag)
+                .body_stream(StaticBody::new(content)),
+            Some(_) => response
+                .header("Vary", "Accept-Encoding")
+                .header("ETag", file.etag)
+                .body_stream(StaticBody::new(file.content)),
+            None => response
+                .header("ETag", file.etag)
+                .body_stream(StaticBody::new(file.content)),
+        };
+
+        Some(response.finalize())
+    }
+}
+
+impl Middleware for StaticFiles {
+    fn before(&mut self, request: &mut Request) -> Option<Response> {
+        self.response(request)
+    }
+}
+
+/// Streams an embedded file, so it doesn't have to be copied to the heap
+struct StaticBody {
+    content: &'static [u8],
+    position: usize,
+}
+
+impl StaticBody {
+    fn new(content: &'static [u8]) -> StaticBody {
+        StaticBody {
+            content,
+            position: 0,
+        }
+    }
+}
+
+impl BodyStream for StaticBody {
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let remaining = &self.content[self.position..];
+        let length = cmp::min(buffer.len(), remaining.len());
+
+        buffer[..length].copy_from_slice(&remaining[..length]);
+        self.position += length;
+        length
+    }
+
+    fn length(&self) -> Option<usize> {
+        Some(self.content.len())
+    }
+}
+
+/// Joins the decoded segments of a path, without empty and `.` segments.
+///
+/// Paths containing `..` are rejected instead of resolved, as nothing outside
+/// of the embedded directory can be reached anyway.
+fn normalize_path<S: AsRef<str>, I: Iterator<Item = S>>(path: I) -> Option<String> {
+    let mut normalized = String::new();
+
+    for segment in path {
+        match segment.as_ref() {
+            "" | "." => {}
+            ".." => return None,
+            // Separators could have been hidden in the encoded path
+            segment if segment.contains(|c: char| c == '/' || c == '\\' || c == '\0') => {
+                return None
+            }
+            segment => {
+                if !normalized.is_empty() {
+                    normalized.push('/');
+                }
+                normalized.push_str(segment);
+            }
+        }
+    }
+
+    Some(normalized)
+}
+
+/// Whether `Accept-Encoding` allows gzip (RFC 7231, 5.3.4)
+///
+/// Codings with `q=0` are refused, a missing header allows only identity.
+fn accepts_gzip(request: &Request) -> bool {
+    let mut gzip = None;
+    let mut any = None;
+
+    for coding in request
+        .headers()
+        .get_all("Accept-Encoding")
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let accepted = params
+            .filter_map(|param| {
+                let param = param.trim();
+                // Slicing could split a character, `get` doesn't panic then
+                let name = param.get(..2);
+                if name.map_or(false, |name| name.eq_ignore_ascii_case("q=")) {
+                    param[2..].trim().parse::<f32>().ok()
+                } else {
+                    None
+                }
+            })
+            .next()
+            .map_or(true, |quality| quality > 0.0);
+
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(accepted);
+        } else if name == "*" {
+            any = Some(accepted);
+        }
+    }
+
+    gzip.or(any).unwrap_or(false)
+}
+
+/// Guesses the `Content-Type` from the file extension.
+pub fn mime_type(path: &str) -> &'static str {
+    let extension = match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => &path[dot + 1..],
+        _ => "",
+    };
+
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::request;
+    use super::*;
+    use alloc::string::ToString;
+
+    static FILES: &[StaticFile] = &[
+        file("index.html", b"index"),
+        file("css/style.css", b"style"),
+        file("docs/index.html", b"docs"),
+    ];
+
+    const fn file(path: &'static str, content: &'static [u8]) -> StaticFile {
+        StaticFile {
+            path,
+            content,
+            etag: "\"1\"",
+            gzip: None,
+        }
+    }
+
+    fn accepts(accept_encoding: &str) -> bool {
+        accepts_gzip(&request(
+            Method::Get,
+            "/",
+            &[("Accept-Encoding", accept_encoding)],
+        ))
+    }
+
+    fn status(method: Method, target: &str) -> Option<Status> {
+        StaticFiles::new(FILES)
+            .response(&request(method, target, &[]))
+            .map(|response| response.status)
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            normalize_path("/css//./style.css".split('/')),
+            Some("css/style.css".to_string())
+        );
+        assert_eq!(normalize_path("/".split('/')), Some(String::new()));
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        for path in &["/..", "/css/../../secret", "/css\\..\\index.html", "/a\0"] {
+            assert_eq!(normalize_path(path.split('/')), None);
+        }
+    }
+
+    #[test]
+    fn rejects_encoded_traversal() {
+        let targets = [
+            "/%2e%2e/secret",
+            "/css/%2E%2E/%2e%2e/secret",
+            "/css%2F..%2Findex.html",
+            "/css%5C..%5Cindex.html",
+        ];
+
+        for target in targets.iter() {
+            let request = request(Method::Get, target, &[]);
+            assert_eq!(normalize_path(request.path_segments()), None);
+            assert_eq!(status(Method::Get, target), Some(Status::BadRequest));
+        }
+    }
+
+    #[test]
+    fn serves_files_and_indexes() {
+        assert_eq!(status(Method::Get, "/css/style.css"), Some(Status::OK));
+        assert_eq!(status(Method::Head, "/"), Some(Status::OK));
+        assert_eq!(status(Method::Get, "/docs/"), Some(Status::OK));
+        assert_eq!(status(Method::Get, "/missing.html"), None);
+        assert_eq!(status(Method::Post, "/css/style.css"), None);
+    }
+
+    #[test]
+    fn redirects_to_directories() {
+        assert_eq!(status(Method::Get, "/docs"), Some(Status::MovedPermanently));
+        assert_eq!(
+            status(Method::Head, "/docs/."),
+            Some(Status::MovedPermanently)
+        );
+
+        let response = StaticFiles::new(FILES)
+            .response(&request(Method::Get, "/docs?page=2", &[]))
+            .unwrap();
+        assert_eq!(response.headers.get("Location"), Some("/docs/?page=2"));
+    }
+
+    #[test]
+    fn negotiates_gzip() {
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, X-GZIP;q=0.5"));
+        assert!(!accepts("deflate"));
+        assert!(!accepts_gzip(&request(Method::Get, "/", &[])));
+    }
+
+    #[test]
+    fn respects_zero_qualities() {
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("gzip; q=0.000, deflate"));
+        assert!(accepts("gzip;q=0.001"));
+    }
+
+    #[test]
+    fn falls_back_to_wildcards() {
+        assert!(accepts("*"));
+        assert!(!accepts("*;q=0"));
+        assert!(!accepts("gzip;q=0, *"));
+        assert!(accepts("*;q=0, gzip"));
+    }
+
+    #[test]
+    fn ignores_non_ascii_parameters() {
+        assert!(accepts("gzip;€=1"));
+        assert!(accepts("gzip;q€"));
+        assert!(accepts("gzip;q=€"));
+        assert!(!accepts("€, *;q=0"));
+    }
+}
@@ -0,0 +1,462 @@
+//! Tests that run the whole server on top of smoltcp's `Loopback` device,
+//! with a client socket in the server's own socket set.
+//!
+//! Also has helpers for the unit tests of the other modules.
+
+extern crate std;
+
+use alloc::{
+    boxed::Box,
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cell::{Cell, RefCell};
+use smoltcp::phy::Loopback;
+use smoltcp::socket::{TcpSocket, TcpSocketBuffer};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress};
+
+use super::{
+    etag, Body, BodyStream, HeaderMap, Limits, Method, Middleware, NetworkConfig, Request,
+    Response, ResponseBuilder, Status, WebSocket, HTTPD,
+};
+
+const PORT: u16 = 80;
+
+// Polls per exchange, servers that don't close the connection by then fail
+const MAX_POLLS: usize = 1000;
+
+std::thread_local! {
+    // Tests run in threads of their own, so each of them gets its own clock
+    static NOW: Cell<Instant> = Cell::new(Instant::from_millis(0));
+}
+
+fn clock() -> Instant {
+    NOW.with(|now| now.get())
+}
+
+fn advance_clock(duration: Duration) {
+    NOW.with(|now| now.set(now.get() + duration));
+}
+
+/// A server on 127.0.0.1 that serves one connection at a time
+fn server<F: FnMut(&Request, &Vec<u8>) -> Response>(routes_callback: F) -> HTTPD<Loopback, F> {
+    let network_config = NetworkConfig::new(IpAddress::v4(127, 0, 0, 1), 8);
+    let ethernet_addr = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+    HTTPD::with_device(
+        Loopback::new(),
+        ethernet_addr,
+        network_config,
+        PORT,
+        1,
+        clock,
+        routes_callback,
+    )
+}
+
+/// Connects a client to `server`, sends `request` and returns everything
+/// the server sent until it closed the connection.
+fn exchange_bytes<F: FnMut(&Request, &Vec<u8>) -> Response>(
+    server: &mut HTTPD<Loopback, F>,
+    request: &[u8],
+) -> Vec<u8> {
+    exchange_parts(server, &[request])
+}
+
+/// Like `exchange_bytes`, but sends every part of the request only after
+/// the server answered the part before.
+fn exchange_parts<F: FnMut(&Request, &Vec<u8>) -> Response>(
+    server: &mut HTTPD<Loopback, F>,
+    parts: &[&[u8]],
+) -> Vec<u8> {
+    let client = TcpSocket::new(
+        TcpSocketBuffer::new(vec![0; 65535]),
+        TcpSocketBuffer::new(vec![0; 65535]),
+    );
+    let handle = server.sockets.add(client);
+    server
+        .sockets
+        .get::<TcpSocket>(handle)
+        .connect((IpAddress::v4(127, 0, 0, 1), PORT), 49152)
+        .unwrap();
+
+    let mut parts = parts.iter().peekable();
+    let mut received = vec![];
+    // How much was received when the last part was sent
+    let mut received_before = None;
+
+    for _ in 0..MAX_POLLS {
+        server.poll().unwrap();
+        advance_clock(Duration::from_millis(10));
+
+        let mut socket = server.sockets.get::<TcpSocket>(handle);
+        let answered = received_before.map_or(true, |length| received.len() > length);
+        if answered && socket.can_send() {
+            if let Some(part) = parts.next() {
+                assert_eq!(socket.send_slice(part).unwrap(), part.len());
+                received_before = Some(received.len());
+            }
+        }
+
+        if socket.can_recv() {
+            socket
+                .recv(|data| {
+                    received.extend_from_slice(data);
+                    (data.len(), ())
+                })
+                .unwrap();
+        }
+
+        if parts.peek().is_none() && !socket.may_recv() {
+            return received;
+        }
+    }
+
+    panic!("Connection wasn't closed, received {:?}", received);
+}
+
+/// Like `exchange_bytes`, for responses that are text only
+fn exchange<F: FnMut(&Request, &Vec<u8>) -> Response>(
+    server: &mut HTTPD<Loopback, F>,
+    request: &str,
+) -> String {
+    String::from_utf8(exchange_bytes(server, request.as_bytes())).unwrap()
+}
+
+/// A request like the parser would create it
+pub(super) fn request(method: Method, target: &str, headers: &[(&str, &str)]) -> Request {
+    let mut header_map = HeaderMap::new();
+    for (key, value) in headers {
+        header_map.append(key, value);
+    }
+
+    Request::new(
+        method,
+        target.to_string(),
+        "HTTP/1.1".to_string(),
+        header_map,
+    )
+}
+
+/// Remembers the status of every response it sees
+struct StatusLog(Rc<RefCell<Vec<Status>>>);
+
+impl Middleware for StatusLog {
+    fn after(&mut self, _request: &Request, response: &mut Response) {
+        self.0.borrow_mut().push(response.status.clone());
+    }
+}
+
+/// Replaces the body of every response
+struct SwapBody(fn() -> Body);
+
+impl Middleware for SwapBody {
+    fn after(&mut self, _request: &Request, response: &mut Response) {
+        response.body = (self.0)();
+    }
+}
+
+/// Streams its content without telling its length
+struct UnknownLength(&'static [u8]);
+
+impl BodyStream for UnknownLength {
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let length = self.0.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&self.0[..length]);
+        self.0 = &self.0[length..];
+        length
+    }
+
+    fn length(&self) -> Option<usize> {
+        None
+    }
+}
+
+fn hello(request: &Request, _body: &Vec<u8>) -> Response {
+    match request.path() {
+        "/hello" => ResponseBuilder::new(Status::OK)
+            .header("Content-Type", "text/plain")
+            .body(b"Hello".to_vec())
+            .etag()
+            .finalize(),
+        _ => ResponseBuilder::new(Status::NotFound).finalize(),
+    }
+}
+
+#[test]
+fn responds_over_loopback() {
+    let mut server = server(hello);
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nContent-Length: 5\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+}
+
+/// Answers with the request body and its trailers
+fn echo(request: &Request, body: &Vec<u8>) -> Response {
+    let mut response = ResponseBuilder::new(Status::OK).body(body.clone());
+
+    for (key, value) in request.trailers().iter() {
+        response = response.header(key, value);
+    }
+
+    response.finalize()
+}
+
+#[test]
+fn decodes_chunked_uploads() {
+    let mut server = server(echo);
+
+    let response = exchange(
+        &mut server,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+         Connection: close\r\n\r\n5;ext=1\r\nHello\r\n7\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nX-Trailer: 1\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello, world"));
+}
+
+#[test]
+fn rejects_unknown_transfer_codings() {
+    for transfer_encoding in &["gzip, chunked", "chunked, chunked", "identity"] {
+        let mut server = server(echo);
+        let response = exchange(
+            &mut server,
+            &format!(
+                "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: {}\r\n\r\n\
+                 0\r\n\r\n",
+                transfer_encoding
+            ),
+        );
+
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+    }
+}
+
+#[test]
+fn closes_connections_with_both_lengths() {
+    let mut server = server(echo);
+
+    let response = exchange(
+        &mut server,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+         Content-Length: 5\r\n\r\n5\r\nHello\r\n0\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+}
+
+#[test]
+fn answers_pipelined_requests_in_order() {
+    let mut server = server(hello);
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+
+    let second = response.find("HTTP/1.1 404 Not Found\r\n").unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response[..second].contains("\r\nConnection: keep-alive\r\n"));
+    assert!(response[..second].ends_with("\r\n\r\nHello"));
+    assert!(response[second..].contains("\r\nConnection: close\r\n"));
+}
+
+#[test]
+fn times_out_incomplete_heads() {
+    let mut server = server(hello);
+
+    let response = exchange(&mut server, "GET /hello HTTP/1.1\r\nHost: localhost\r\n");
+
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+}
+
+#[test]
+fn closes_idle_connections() {
+    let mut server = server(hello);
+    server.set_limits(Limits {
+        idle_timeout: Duration::from_secs(1),
+        ..Limits::default()
+    });
+
+    assert_eq!(exchange(&mut server, ""), "");
+}
+
+#[test]
+fn rejects_too_many_headers() {
+    let mut server = server(hello);
+    server.set_limits(Limits {
+        max_header_count: 2,
+        ..Limits::default()
+    });
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\nA: 1\r\nB: 2\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+}
+
+#[test]
+fn sends_continue_before_bodies() {
+    let mut server = server(echo);
+
+    let response = exchange_parts(
+        &mut server,
+        &[
+            b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+              Expect: 100-continue\r\nConnection: close\r\n\r\n",
+            b"Hello",
+        ],
+    );
+    let response = String::from_utf8(response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+}
+
+#[test]
+fn rejects_announced_bodies_that_are_too_large() {
+    let mut server = server(echo);
+    server.set_limits(Limits {
+        max_body_size: 4,
+        ..Limits::default()
+    });
+
+    let response = exchange(
+        &mut server,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+         Expect: 100-continue\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    assert!(!response.contains("100 Continue"));
+}
+
+#[test]
+fn rejects_unknown_expectations() {
+    let mut server = server(echo);
+
+    let response = exchange(
+        &mut server,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+         Expect: foo\r\n\r\nHello",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+}
+
+#[test]
+fn upgrades_to_websocket() {
+    let mut server = server(|request, _body| WebSocket::handshake(request));
+
+    let mut request = b"GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+        Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n"
+        .to_vec();
+    // A masked ping with "hi" and a close frame, right after the handshake
+    request.extend_from_slice(&[0x89, 0x82, 0, 0, 0, 0, b'h', b'i']);
+    request.extend_from_slice(&[0x88, 0x80, 0, 0, 0, 0]);
+
+    let response = exchange_bytes(&mut server, &request);
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap()
+        + 4;
+    let head = String::from_utf8(response[..head_end].to_vec()).unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(head.contains("\r\nConnection: Upgrade\r\n"));
+    // The pong and the echoed close frame
+    assert_eq!(
+        response[head_end..],
+        [0x8A, 0x02, b'h', b'i', 0x88, 0x02, 0x03, 0xE8]
+    );
+}
+
+#[test]
+fn middlewares_see_not_modified() {
+    let statuses = Rc::new(RefCell::new(vec![]));
+    let mut server = server(hello);
+    server.add_middleware(StatusLog(statuses.clone()));
+
+    let response = exchange(
+        &mut server,
+        &format!(
+            "GET /hello HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\n\
+             Connection: close\r\n\r\n",
+            etag(b"Hello")
+        ),
+    );
+
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+    assert_eq!(*statuses.borrow(), [Status::NotModified]);
+}
+
+#[test]
+fn middlewares_see_partial_content() {
+    let statuses = Rc::new(RefCell::new(vec![]));
+    let mut server = server(hello);
+    server.add_middleware(StatusLog(statuses.clone()));
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\nRange: bytes=1-3\r\nConnection: close\r\n\r\n",
+    );
+
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(response.contains("\r\nContent-Range: bytes 1-3/5\r\n"));
+    assert!(response.ends_with("\r\n\r\nell"));
+    assert_eq!(*statuses.borrow(), [Status::PartialContent]);
+}
+
+#[test]
+fn announces_the_length_of_swapped_bodies() {
+    let mut server = server(hello);
+    server.add_middleware(SwapBody(|| Body::Bytes(b"Hi".to_vec())));
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+
+    assert!(response.contains("\r\nContent-Length: 2\r\n"));
+    assert!(!response.contains("Content-Length: 5"));
+    assert!(response.ends_with("\r\n\r\nHi"));
+}
+
+#[test]
+fn drops_the_length_of_chunked_bodies() {
+    let mut server = server(hello);
+    server.add_middleware(SwapBody(|| Body::Stream(Box::new(UnknownLength(b"Hi")))));
+
+    let response = exchange(
+        &mut server,
+        "GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+
+    assert!(response.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!response.contains("Content-Length"));
+    assert!(response.ends_with("\r\n\r\n2\r\nHi\r\n0\r\n\r\n"));
+}
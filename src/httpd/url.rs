@@ -0,0 +1,91 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// Decodes `%XX` escapes, invalid escapes are kept as they are.
+///
+/// With `plus_as_space`, `+` is decoded to a space like in HTML forms.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        i += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Parses `a=1&b=2&a=3` into a map of keys to all of their values, in order.
+pub fn parse_query(query: &str) -> BTreeMap<String, Vec<String>> {
+    let mut parameters = BTreeMap::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = match pair.find('=') {
+            Some(separator) => (&pair[..separator], &pair[separator + 1..]),
+            None => (pair, ""),
+        };
+
+        parameters
+            .entry(percent_decode(key, true))
+            .or_insert_with(Vec::new)
+            .push(percent_decode(value, true));
+    }
+
+    parameters
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc", false), "a b/c");
+        assert_eq!(percent_decode("%e2%82%AC", false), "€");
+    }
+
+    #[test]
+    fn keeps_invalid_escapes() {
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%4", false), "%4");
+        assert_eq!(percent_decode("%zz%41", false), "%zzA");
+        assert_eq!(percent_decode("%FF", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn decodes_plus_in_queries_only() {
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("a+b%2B", true), "a b+");
+    }
+
+    #[test]
+    fn parses_query() {
+        let query = parse_query("a=1&b=x+y&a=%32&&flag&=empty&c=d=e");
+
+        assert_eq!(query.len(), 5);
+        assert_eq!(query["a"], ["1", "2"]);
+        assert_eq!(query["b"], ["x y"]);
+        assert_eq!(query["c"], ["d=e"]);
+        assert_eq!(query["flag"], [""]);
+        assert_eq!(query[""], ["empty"]);
+    }
+}
@@ -0,0 +1,613 @@
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cmp;
+use log::debug;
+use smoltcp::socket::TcpSocket;
+
+use super::error::Error;
+use super::request::Request;
+use super::response::{Response, ResponseBuilder};
+use super::send_buffer::SendBuffer;
+use super::sha1::sha1;
+use super::status::Status;
+
+// Appended to the client's key for `Sec-WebSocket-Accept` (RFC 6455, 1.3)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Received messages the application didn't read yet, older ones are dropped
+const MAX_QUEUED_MESSAGES: usize = 16;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+// Status codes of close frames (RFC 6455, 7.4.1)
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// A connection that was upgraded to the WebSocket protocol.
+///
+/// Ping, pong and close frames are answered by the server, the application
+/// only sees complete (defragmented) data messages.
+#[derive(Debug)]
+pub struct WebSocket {
+    path: String,
+    messages: VecDeque<Message>,
+    output: SendBuffer,
+    // Opcode and payload of a fragmented message that isn't complete yet
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+    ping_sent: bool,
+}
+
+impl WebSocket {
+    /// Answers a WebSocket handshake, for use in a route.
+    ///
+    /// The connection is upgraded after the response is sent and shows up in
+    /// `HTTPD::websockets`. Invalid handshakes are rejected.
+    pub fn handshake(request: &Request) -> Response {
+        let headers = request.headers();
+
+        if !headers.contains_token("Sec-WebSocket-Version", "13") {
+            return ResponseBuilder::new(Status::UpgradeRequired)
+                .header("Sec-WebSocket-Version", "13")
+                .finalize();
+        }
+
+        let key = headers.get("Sec-WebSocket-Key").unwrap_or_default().trim();
+
+        if request.version() != "HTTP/1.1"
+            || !headers.contains_token("Upgrade", "websocket")
+            || !headers.connection("Upgrade")
+            || !is_valid_key(key)
+        {
+            return ResponseBuilder::new(Status::BadRequest).finalize();
+        }
+
+        let mut accept = String::from(key);
+        accept.push_str(ACCEPT_GUID);
+
+        ResponseBuilder::new(Status::SwitchingProtocols)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Accept", base64(&sha1(accept.as_bytes())))
+            .finalize()
+    }
+
+    /// The WebSocket for a handshake response, if it accepts the upgrade
+    pub(super) fn upgrade(request: &Request, response: &Response) -> Option<WebSocket> {
+        if response.status != Status::SwitchingProtocols
+            || !response.headers.contains_token("Upgrade", "websocket")
+        {
+            return None;
+        }
+
+        Some(WebSocket {
+            path: request.path().to_string(),
+            messages: VecDeque::new(),
+            output: SendBuffer::new(),
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+            ping_sent: false,
+        })
+    }
+
+    /// Path of the handshake request, to tell endpoints apart
+    #[allow(dead_code)]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether messages can still be sent
+    pub fn is_open(&self) -> bool {
+        !self.close_sent && !self.close_received
+    }
+
+    /// The next message the client sent, if any
+    pub fn receive(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    /// Queues a message, it is sent when the server is polled.
+    ///
+    /// Messages for closing connections are dropped. So are messages while
+    /// too much is queued already, and the connection is aborted then.
+    pub fn send(&mut self, message: Message) {
+        if !self.is_open() || !self.output.has_room() {
+            return;
+        }
+
+        match message {
+            Message::Text(text) => self.send_frame(OPCODE_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.send_frame(OPCODE_BINARY, &data),
+        }
+    }
+
+    /// Starts the closing handshake, the connection is closed once the
+    /// client answers. `code` is usually 1000 for a normal closure.
+    pub fn close(&mut self, code: u16, reason: &str) {
+        if self.close_sent {
+            return;
+        }
+
+        // Control frames can't be longer than 125 bytes, the reason is cut
+        // between characters
+        let mut length = cmp::min(reason.len(), 123);
+        while !reason.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend(reason[..length].bytes());
+
+        self.send_frame(OPCODE_CLOSE, &payload);
+        self.close_sent = true;
+    }
+
+    /// Checks that the client is still there, returns false if it didn't
+    /// send anything since the last ping or didn't answer our close frame.
+    pub(super) fn ping(&mut self) -> bool {
+        // Nothing may be sent after a close frame (RFC 6455, 5.5.1)
+        if self.ping_sent || self.close_sent {
+            return false;
+        }
+
+        self.send_frame(OPCODE_PING, &[]);
+        self.ping_sent = true;
+        true
+    }
+
+    /// Whether the closing handshake is done and the TCP connection can be
+    /// closed
+    pub(super) fn is_closed(&self) -> bool {
+        self.close_sent && self.close_received && self.output.is_empty()
+    }
+
+    /// Handles all complete frames in `input`.
+    pub(super) fn receive_frames(&mut self, input: &mut Vec<u8>, max_message_size: usize) {
+        while !self.close_received {
+            let result = match parse_frame(input, max_message_size) {
+                Ok(Some((frame, length))) => {
+                    input.drain(..length);
+                    // Any frame shows that the client is still there
+                    self.ping_sent = false;
+                    self.handle_frame(frame, max_message_size)
+                }
+                Ok(None) => break,
+                Err(code) => Err(code),
+            };
+
+            if let Err(code) = result {
+                // Nothing the client sends afterwards can be understood
+                debug!("WebSocket failed with {}", code);
+                self.close(code, "");
+                self.close_received = true;
+            }
+        }
+
+        if self.close_received {
+            input.clear();
+        }
+    }
+
+    /// Sends as much of the queued frames as possible.
+    pub(super) fn send_output(&mut self, socket: &mut TcpSocket) -> Result<usize, Error> {
+        self.output.send(socket)
+    }
+
+    fn handle_frame(&mut self, frame: Frame, max_message_size: usize) -> Result<(), u16> {
+        match frame.opcode {
+            OPCODE_TEXT | OPCODE_BINARY if self.fragments.is_some() => Err(CLOSE_PROTOCOL_ERROR),
+            OPCODE_TEXT | OPCODE_BINARY if frame.fin => {
+                self.push_message(frame.opcode, frame.payload)
+            }
+            OPCODE_TEXT | OPCODE_BINARY => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(())
+            }
+            OPCODE_CONTINUATION => {
+                let (opcode, mut payload) = self.fragments.take().ok_or(CLOSE_PROTOCOL_ERROR)?;
+
+                if payload.len() + frame.payload.len() > max_message_size {
+                    return Err(CLOSE_TOO_BIG);
+                }
+                payload.extend(frame.payload);
+
+                if frame.fin {
+                    self.push_message(opcode, payload)
+                } else {
+                    self.fragments = Some((opcode, payload));
+                    Ok(())
+                }
+            }
+            OPCODE_CLOSE => {
+                self.close_received = true;
+
+                // The client's status code is echoed (RFC 6455, 5.5.1)
+                match frame.payload.len() {
+                    0 => self.close(CLOSE_NORMAL, ""),
+                    1 => return Err(CLOSE_PROTOCOL_ERROR),
+                    _ => self.close(u16::from_be_bytes([frame.payload[0], frame.payload[1]]), ""),
+                }
+                Ok(())
+            }
+            OPCODE_PING => {
+                if !self.close_sent {
+                    self.send_frame(OPCODE_PONG, &frame.payload);
+                }
+                Ok(())
+            }
+            OPCODE_PONG => Ok(()),
+            _ => Err(CLOSE_PROTOCOL_ERROR),
+        }
+    }
+
+    fn push_message(&mut self, opcode: u8, payload: Vec<u8>) -> Result<(), u16> {
+        let message = if opcode == OPCODE_TEXT {
+            Message::Text(String::from_utf8(payload).map_err(|_| CLOSE_INVALID_DATA)?)
+        } else {
+            Message::Binary(payload)
+        };
+
+        if self.messages.len() == MAX_QUEUED_MESSAGES {
+            debug!("Dropping unread WebSocket message");
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+
+        Ok(())
+    }
+
+    /// Queues a single unmasked frame, servers don't mask (RFC 6455, 5.1)
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) {
+        self.output.push(0x80 | opcode);
+
+        match payload.len() {
+            length if length < 126 => self.output.push(length as u8),
+            length if length <= 0xFFFF => {
+                self.output.push(126);
+                self.output.extend(&(length as u16).to_be_bytes());
+            }
+            length => {
+                self.output.push(127);
+                self.output.extend(&(length as u64).to_be_bytes());
+            }
+        }
+
+        self.output.extend(payload);
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Parses the frame at the start of `input` and returns it with its length,
+/// `None` if it isn't complete yet (RFC 6455, 5.2).
+///
+/// Errors are the status code to close the connection with.
+fn parse_frame(input: &[u8], max_message_size: usize) -> Result<Option<(Frame, usize)>, u16> {
+    if input.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin = input[0] & 0x80 != 0;
+    let opcode = input[0] & 0x0F;
+    let masked = input[1] & 0x80 != 0;
+
+    // No extensions are negotiated, so the reserved bits must not be set.
+    // Clients always have to mask their frames
+    if input[0] & 0x70 != 0 || !masked {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+
+    let (length, header_length) = match input[1] & 0x7F {
+        126 if input.len() >= 4 => (u16::from_be_bytes([input[2], input[3]]) as u64, 4),
+        127 if input.len() >= 10 => {
+            let mut length = [0; 8];
+            length.copy_from_slice(&input[2..10]);
+            (u64::from_be_bytes(length), 10)
+        }
+        126 | 127 => return Ok(None),
+        length => (u64::from(length), 2),
+    };
+
+    // Control frames can't be fragmented or longer than 125 bytes
+    if opcode & 0x08 != 0 && (!fin || length > 125) {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+
+    if length > max_message_size as u64 {
+        return Err(CLOSE_TOO_BIG);
+    }
+
+    let length = length as usize;
+    let payload_start = header_length + 4;
+    if input.len() < payload_start + length {
+        return Ok(None);
+    }
+
+    let mask = &input[header_length..payload_start];
+    let payload = input[payload_start..payload_start + length]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        payload_start + length,
+    )))
+}
+
+/// Keys are 16 random bytes in base64 (RFC 6455, 4.1)
+fn is_valid_key(key: &str) -> bool {
+    key.len() == 24
+        && key.ends_with("==")
+        && key[..22]
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'+' || byte == b'/')
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            group.get(1).cloned().unwrap_or(0),
+            group.get(2).cloned().unwrap_or(0),
+        ];
+        let bits = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::headers::HeaderMap;
+    use super::super::method::Method;
+    use super::*;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    /// A frame as clients send it, always masked
+    fn masked_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first_byte];
+        match payload.len() {
+            length if length < 126 => frame.push(0x80 | length as u8),
+            length => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ MASK[i % 4]),
+        );
+        frame
+    }
+
+    fn handshake_request(key: &str) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.append("Host", "server.example.com");
+        headers.append("Upgrade", "websocket");
+        headers.append("Connection", "keep-alive, Upgrade");
+        headers.append("Sec-WebSocket-Key", key);
+        headers.append("Sec-WebSocket-Version", "13");
+
+        Request::new(
+            Method::Get,
+            "/chat".to_string(),
+            "HTTP/1.1".to_string(),
+            headers,
+        )
+    }
+
+    fn websocket() -> WebSocket {
+        let request = handshake_request("dGhlIHNhbXBsZSBub25jZQ==");
+        WebSocket::upgrade(&request, &WebSocket::handshake(&request)).unwrap()
+    }
+
+    #[test]
+    fn encodes_base64() {
+        // Test vectors of RFC 4648, 10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (data, encoded) in vectors.iter() {
+            assert_eq!(base64(data.as_bytes()), *encoded);
+        }
+    }
+
+    #[test]
+    fn accepts_rfc_6455_key() {
+        let response = WebSocket::handshake(&handshake_request("dGhlIHNhbXBsZSBub25jZQ=="));
+
+        assert_eq!(response.status, Status::SwitchingProtocols);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_handshakes() {
+        let response = WebSocket::handshake(&handshake_request("short"));
+        assert_eq!(response.status, Status::BadRequest);
+
+        let mut request = handshake_request("dGhlIHNhbXBsZSBub25jZQ==");
+        request.headers_mut().insert("Sec-WebSocket-Version", "8");
+        let response = WebSocket::handshake(&request);
+        assert_eq!(response.status, Status::UpgradeRequired);
+        assert_eq!(response.headers.get("Sec-WebSocket-Version"), Some("13"));
+    }
+
+    #[test]
+    fn parses_masked_frames() {
+        // "Hello" from RFC 6455, 5.7
+        let input = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+
+        for end in 0..input.len() {
+            assert!(parse_frame(&input[..end], 1024).unwrap().is_none());
+        }
+
+        let (frame, length) = parse_frame(&input, 1024).unwrap().unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(length, input.len());
+    }
+
+    #[test]
+    fn parses_extended_lengths() {
+        let payload = [0x42; 256];
+        let input = masked_frame(0x82, &payload);
+
+        assert!(parse_frame(&input[..3], 1024).unwrap().is_none());
+        assert!(parse_frame(&input[..input.len() - 1], 1024)
+            .unwrap()
+            .is_none());
+
+        let (frame, length) = parse_frame(&input, 1024).unwrap().unwrap();
+        assert_eq!(frame.payload[..], payload[..]);
+        assert_eq!(length, 4 + 4 + 256);
+
+        let mut input = vec![0x82, 0x80 | 127];
+        input.extend_from_slice(&(1u64 << 32).to_be_bytes());
+        input.extend_from_slice(&MASK);
+        assert_eq!(parse_frame(&input, 1024).err(), Some(CLOSE_TOO_BIG));
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        // Unmasked
+        assert_eq!(
+            parse_frame(&[0x81, 0x00], 1024).err(),
+            Some(CLOSE_PROTOCOL_ERROR)
+        );
+        // Reserved bits set
+        assert_eq!(
+            parse_frame(&masked_frame(0xC1, b""), 1024).err(),
+            Some(CLOSE_PROTOCOL_ERROR)
+        );
+        // Fragmented and long control frames
+        assert_eq!(
+            parse_frame(&masked_frame(0x09, b""), 1024).err(),
+            Some(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            parse_frame(&masked_frame(0x89, &[0; 126]), 1024).err(),
+            Some(CLOSE_PROTOCOL_ERROR)
+        );
+        assert_eq!(
+            parse_frame(&masked_frame(0x81, &[b'a'; 11]), 10).err(),
+            Some(CLOSE_TOO_BIG)
+        );
+    }
+
+    #[test]
+    fn joins_fragmented_messages() {
+        let mut websocket = websocket();
+        let mut input = masked_frame(0x01, b"Hel");
+        // Control frames may come between fragments
+        input.extend(masked_frame(0x89, b""));
+        input.extend(masked_frame(0x80, b"lo"));
+        input.extend(masked_frame(0x82, &[1, 2]));
+
+        websocket.receive_frames(&mut input, 1024);
+
+        assert!(input.is_empty());
+        assert_eq!(
+            websocket.receive(),
+            Some(Message::Text("Hello".to_string()))
+        );
+        assert_eq!(websocket.receive(), Some(Message::Binary(vec![1, 2])));
+        assert_eq!(websocket.receive(), None);
+        // The pong
+        assert_eq!(websocket.output.len(), 2);
+    }
+
+    #[test]
+    fn closes_on_invalid_text() {
+        let mut websocket = websocket();
+        let mut input = masked_frame(0x81, &[0xff]);
+
+        websocket.receive_frames(&mut input, 1024);
+
+        assert!(!websocket.is_open());
+        assert_eq!(websocket.receive(), None);
+        // A close frame with 1007
+        assert_eq!(websocket.output.len(), 4);
+    }
+
+    #[test]
+    fn answers_close_frames() {
+        let mut websocket = websocket();
+        let mut input = masked_frame(0x88, &CLOSE_NORMAL.to_be_bytes());
+        input.extend(masked_frame(0x81, b"ignored"));
+
+        websocket.receive_frames(&mut input, 1024);
+
+        assert!(!websocket.is_open());
+        assert!(!websocket.ping());
+        assert_eq!(websocket.receive(), None);
+        // The echoed close frame, nothing may be sent after it
+        websocket.send(Message::Text("late".to_string()));
+        assert_eq!(websocket.output.len(), 4);
+    }
+
+    #[test]
+    fn cuts_close_reasons_between_characters() {
+        let mut websocket = websocket();
+        websocket.close(CLOSE_NORMAL, &"ä".repeat(100));
+
+        // Two bytes of status code and 61 characters of two bytes each
+        assert_eq!(websocket.output.len(), 2 + 2 + 122);
+    }
+}
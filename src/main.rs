@@ -12,14 +12,14 @@ extern crate alloc;
 mod httpd;
 mod logger;
 
-use alloc::{str, string::ToString, vec::Vec};
+use alloc::{str, vec::Vec};
 use alloc_cortex_m::CortexMHeap;
 use core::alloc::Layout as AllocLayout;
 use core::cell::RefCell;
 use core::panic::PanicInfo;
 use cortex_m::asm;
 use cortex_m_rt::{entry, exception};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use smoltcp::wire::{EthernetAddress, IpAddress, Ipv4Address};
 use stm32f7::stm32f7x6::{CorePeripherals, Peripherals};
 use stm32f7_discovery::gpio::{GpioPort, OutputPin};
@@ -27,7 +27,10 @@ use stm32f7_discovery::lcd::{self, Color, Framebuffer, Layer};
 use stm32f7_discovery::system_clock::{self, Hz};
 use stm32f7_discovery::{init, touch};
 
-use httpd::{Request, ResponseBuilder, Routes, Status, HTTPD};
+use httpd::{
+    AccessLog, Cors, Event, EventStream, Message, Method, NetworkConfig, Request, ResponseBuilder,
+    Routes, ServerHeader, StaticFile, StaticFiles, Status, WebSocket, HTTPD,
+};
 
 const SYSTICK: Hz = Hz(100);
 
@@ -35,11 +38,16 @@ const HEAP_SIZE: usize = 50 * 1024;
 
 const ETH_ADDR: EthernetAddress = EthernetAddress([0x00, 0x08, 0xDC, 0xAB, 0xCD, 0xEF]);
 const IP_ADDR: IpAddress = IpAddress::Ipv4(Ipv4Address([192, 168, 1, 42]));
+const PREFIX_LEN: u8 = 24;
+const GATEWAY: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
 const PORT: u16 = 80;
+const MAX_CONNECTIONS: usize = 4;
 
-const INDEX_PAGE: &str = include_str!("httpd/index.html");
 const NOTFOUND_PAGE: &str = include_str!("httpd/notfound.html");
 
+// The frontend, generated from `static/` by the build script
+static STATIC_FILES: &[StaticFile] = include!(concat!(env!("OUT_DIR"), "/static_files.rs"));
+
 #[entry]
 fn main() -> ! {
     let core_peripherals = CorePeripherals::take().unwrap();
@@ -108,21 +116,13 @@ fn main() -> ! {
 
     // Wrap layer 1 so it can be used in request_handler and touch handler
     let layer_wrapper = RefCell::new(layer_1);
-    // Pixels to send next time the client polls
-    let new_pixels = RefCell::new(vec![]);
 
     // Gets called on each request
 
-    let mut request_handler = |req: &Request, body: &Vec<u8>| {
+    let request_handler = |req: &Request, body: &Vec<u8>| {
         Routes::init(req)
-            // Frontend route
-            .route("GET", "/", |_req, _args| {
-                ResponseBuilder::new(Status::OK)
-                    .body_html(INDEX_PAGE)
-                    .finalize()
-            })
             // API routes
-            .route("GET", "/pins", |_req, _args| {
+            .route(Method::Get, "/pins", |_req, _args| {
                 let pins_body =
                     format!("{{ \"led\": {led}, \"backlight\": {backlight}, \"display_enable\": {display_enable} }}\n",
                         led = pins.led.get(),
@@ -135,7 +135,7 @@ fn main() -> ! {
                     .body(pins_body.as_bytes().to_vec())
                     .finalize()
             })
-            .route("POST", "/pins/:name", |_req, args| {
+            .route(Method::Post, "/pins/:name", |_req, args| {
                 let pin_to_toggle: Option<&mut OutputPin> = match args.get("name").unwrap().as_str() {
                     "led" => Some(&mut pins.led),
                     "backlight" => Some(&mut pins.backlight),
@@ -165,33 +165,25 @@ fn main() -> ! {
                     _ => ResponseBuilder::new(Status::BadRequest).finalize(),
                 }
             })
-            .route("POST", "/pixels", |_req, _args| {
+            .route(Method::Post, "/pixels", |_req, _args| {
                 let mut body_iter = body.iter();
 
                 while let (Some(x), Some(y)) = (body_iter.next(), body_iter.next()) {
                     draw_pixel(&mut layer_wrapper.borrow_mut(), *x as usize, *y as usize);
                 }
 
-                let mut pixel_data = vec![];
-                for (x, y) in new_pixels.borrow().iter() {
-                    pixel_data.push(*x);
-                    pixel_data.push(*y);
-                }
-
-                let res = ResponseBuilder::new(Status::OK)
-                    .header("Content-Type", "application/octet-stream")
-                    .body(pixel_data)
-                    .finalize();
-
-                new_pixels.borrow_mut().clear();
-                res
+                ResponseBuilder::new(Status::OK).finalize()
             })
-            .route("POST", "/pixels/clear", |_req, _args| {
-                new_pixels.borrow_mut().clear();
+            // Pushes touch events as "x,y", for clients without WebSockets
+            .route(Method::Get, "/pixels/events", |_req, _args| EventStream::response())
+            // Pushes touch events and receives drawn pixels, two bytes each
+            .route(Method::Get, "/pixels/ws", |req, _args| WebSocket::handshake(req))
+            .route(Method::Post, "/pixels/clear", |_req, _args| {
                 layer_wrapper.borrow_mut().clear();
 
                 ResponseBuilder::new(Status::OK).finalize()
             })
+            .method_not_allowed()
             .catch_all(|_req, _args| {
                 ResponseBuilder::new(Status::NotFound)
                     .body_html(NOTFOUND_PAGE)
@@ -205,38 +197,70 @@ fn main() -> ! {
         &mut ethernet_mac,
         ethernet_dma,
         ETH_ADDR,
-        IP_ADDR,
+        NetworkConfig::new(IP_ADDR, PREFIX_LEN).gateway(GATEWAY),
         PORT,
-        // server "middleware"
-        // sets CORS headers, Server header and prints a nice message
-        |req: &Request, body: &Vec<u8>| {
-            let mut res = request_handler(req, body);
-            res.headers
-                .insert("Access-Control-Allow-Origin".to_string(), "*".to_string());
-            res.headers.insert(
-                "Server".to_string(),
-                format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-            );
-
-            let req_text = format!("{} {}", req.method(), req.path());
-            let (status_num, status_txt) = res.status.numerical_and_text();
-            let res_text = format!("[{} {}]", status_num, status_txt);
-            let empty_space = 50 - req_text.len() - res_text.len();
-
-            info!("{}{}{}", req_text, " ".repeat(empty_space), res_text);
-
-            res
-        },
+        MAX_CONNECTIONS,
+        request_handler,
     )
     .expect("HTTPD initialisation failed");
 
-    info!("Server initialized on {}:{}", IP_ADDR, PORT);
+    // The access log comes first, so that it logs the response as it is sent
+    server.add_middleware(AccessLog);
+    // Lets other origins toggle pins with JSON bodies, too
+    server.add_middleware(
+        Cors::new()
+            .methods(&[Method::Get, Method::Head, Method::Post])
+            .header("Content-Type")
+            .max_age(600),
+    );
+    server.add_middleware(ServerHeader::new(&format!(
+        "{}/{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )));
+    // Frontend files are served in place of routes with the same path
+    server.add_middleware(StaticFiles::new(STATIC_FILES));
+
+    // IP_ADDR is used until the DHCP server hands out a lease
+    server.enable_dhcp();
+
+    info!(
+        "Server initialized on port {}, requesting a DHCP lease",
+        PORT
+    );
 
     info!("Entering loop");
 
+    let mut leased_address = None;
+
     loop {
         // poll packets and answer them
-        server.poll();
+        if let Err(e) = server.poll() {
+            warn!("Connection aborted, {}", e);
+        }
+
+        // the static address is replaced once a lease is acquired
+        if let Some(address) = server.dhcp_lease().map(|lease| lease.address.address()) {
+            if leased_address != Some(address) {
+                info!("Serving on {}:{}", address, PORT);
+                leased_address = Some(address);
+            }
+        }
+
+        // draw pixels sent over WebSockets
+        for websocket in server.websockets() {
+            while let Some(message) = websocket.receive() {
+                if let Message::Binary(pixels) = message {
+                    for pixel in pixels.chunks_exact(2) {
+                        draw_pixel(
+                            &mut layer_wrapper.borrow_mut(),
+                            pixel[0] as usize,
+                            pixel[1] as usize,
+                        );
+                    }
+                }
+            }
+        }
 
         // poll for touch events and draw stuff
         for touch_event in &touch::touches(&mut i2c_3).expect("Could not read touch events") {
@@ -250,9 +274,11 @@ fn main() -> ! {
 
             let px = (px_x as u8, px_y as u8);
 
-            if !new_pixels.borrow().contains(&px) {
-                new_pixels.borrow_mut().push(px);
+            for websocket in server.websockets() {
+                websocket.send(Message::Binary(vec![px.0, px.1]));
             }
+
+            server.publish(Event::new(&format!("{},{}", px.0, px.1)).name("pixel"));
         }
     }
 }